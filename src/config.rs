@@ -1,5 +1,10 @@
+use crate::logging::{LogFormat, LogTarget};
+use crate::network;
+use crate::systemd;
 use serde::{Deserialize, Serialize};
-use std::net::IpAddr;
+use std::ffi::CString;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 use anyhow::{Context, Result, bail};
 use std::collections::HashSet;
@@ -9,19 +14,387 @@ pub struct Config {
     pub dns_servers: Vec<DnsServer>,
     pub mode: OperationMode,
     pub execution_interval_seconds: u64,
+    /// Benchmark and hybrid modes only: how often the selected servers are health checked between benchmarks
+    pub health_check_interval_seconds: Option<u64>,
     #[serde(default = "default_timeout_seconds")]
     pub timeout_seconds: u64,
     pub resolv_conf_path: Option<String>,
+    #[serde(default)]
+    pub watch: WatchConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    /// Where the daemon keeps server health across restarts, `null` to disable
+    #[serde(default = "default_state_file")]
+    pub state_file: Option<String>,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    /// Unix socket for `autodns status` and friends, `null` to disable
+    #[serde(default = "default_control_socket")]
+    pub control_socket: Option<String>,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    #[serde(default)]
+    pub webhook: WebhookConfig,
+    /// `text` or `json`, overridden by --log-format
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// `stderr`, `journald` or `syslog`, overridden by --log-target
+    #[serde(default)]
+    pub log_target: LogTarget,
+    /// `ranked` or `diverse`: whether to spread the selection across providers
+    #[serde(default)]
+    pub selection_policy: SelectionPolicy,
+    /// Which address families to use: `any`, `ipv4_only`, `ipv6_only`, `prefer_ipv6` or `one_of_each`
+    #[serde(default)]
+    pub family_policy: FamilyPolicy,
+    /// What to write to resolv.conf when every server is offline
+    #[serde(default)]
+    pub on_all_offline: AllOfflinePolicy,
+    /// Written when every server is offline, with `on_all_offline: fallback`
+    #[serde(default)]
+    pub fallback_servers: Vec<IpAddr>,
+    #[serde(default)]
+    pub connectivity: ConnectivityConfig,
 }
 
+/// Nameservers the resolver uses from resolv.conf (MAXNS in glibc)
+pub const MAX_NAMESERVERS: usize = 3;
+
+/// Longest interval or cooldown, so that deadlines computed from them stay on the clock
+pub const MAX_INTERVAL_SECONDS: u64 = 366 * 24 * 60 * 60;
+
 fn default_timeout_seconds() -> u64 {
     2
 }
 
+fn default_state_file() -> Option<String> {
+    Some("/var/lib/autodns/state.json".to_string())
+}
+
+fn default_control_socket() -> Option<String> {
+    Some("/run/autodns/autodns.sock".to_string())
+}
+
+/// Reaction to resolv.conf being modified by another tool
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WatchConfig {
+    #[serde(default = "default_watch_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub policy: ExternalChangePolicy,
+    #[serde(default = "default_watch_backoff_seconds")]
+    pub backoff_seconds: u64,
+}
+
+fn default_watch_enabled() -> bool {
+    true
+}
+
+fn default_watch_backoff_seconds() -> u64 {
+    60
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_watch_enabled(),
+            policy: ExternalChangePolicy::default(),
+            backoff_seconds: default_watch_backoff_seconds(),
+        }
+    }
+}
+
+/// Check that the host itself is online before blaming the DNS servers
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ConnectivityConfig {
+    #[serde(default = "default_connectivity_enabled")]
+    pub enabled: bool,
+    /// Reached with a TCP connection, any of them will do; without targets
+    /// only the routes are checked
+    #[serde(default)]
+    pub targets: Vec<SocketAddr>,
+    #[serde(default = "default_connectivity_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+fn default_connectivity_enabled() -> bool {
+    true
+}
+
+fn default_connectivity_timeout_seconds() -> u64 {
+    2
+}
+
+impl Default for ConnectivityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_connectivity_enabled(),
+            targets: Vec::new(),
+            timeout_seconds: default_connectivity_timeout_seconds(),
+        }
+    }
+}
+
+/// Prometheus metrics endpoint of the daemon
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MetricsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_metrics_listen")]
+    pub listen: SocketAddr,
+}
+
+fn default_metrics_listen() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 9953))
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: default_metrics_listen(),
+        }
+    }
+}
+
+/// Commands run by the daemon when something noteworthy happens
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct HooksConfig {
+    /// The selected servers written to resolv.conf changed
+    pub on_change: Option<String>,
+    /// No configured server is online anymore
+    pub on_all_offline: Option<String>,
+    /// Servers are online again after all of them were offline
+    pub on_recovery: Option<String>,
+    /// resolv.conf could not be written
+    pub on_write_error: Option<String>,
+    /// Hooks still running after this long are killed
+    #[serde(default = "default_hook_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+fn default_hook_timeout_seconds() -> u64 {
+    30
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            on_change: None,
+            on_all_offline: None,
+            on_recovery: None,
+            on_write_error: None,
+            timeout_seconds: default_hook_timeout_seconds(),
+        }
+    }
+}
+
+/// Notifications POSTed to an HTTP endpoint when the selection changes, all
+/// servers go offline or they recover
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WebhookConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    pub url: Option<String>,
+    #[serde(default)]
+    pub template: WebhookTemplate,
+    #[serde(default = "default_webhook_timeout_seconds")]
+    pub timeout_seconds: u64,
+    /// Attempts after the first one failed
+    #[serde(default = "default_webhook_retries")]
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every further one
+    #[serde(default = "default_webhook_retry_backoff_seconds")]
+    pub retry_backoff_seconds: u64,
+    /// Notifications beyond this many per hour are dropped
+    #[serde(default = "default_webhook_max_per_hour")]
+    pub max_per_hour: usize,
+}
+
+fn default_webhook_timeout_seconds() -> u64 {
+    10
+}
+
+fn default_webhook_retries() -> u32 {
+    3
+}
+
+fn default_webhook_retry_backoff_seconds() -> u64 {
+    5
+}
+
+fn default_webhook_max_per_hour() -> usize {
+    30
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: None,
+            template: WebhookTemplate::default(),
+            timeout_seconds: default_webhook_timeout_seconds(),
+            retries: default_webhook_retries(),
+            retry_backoff_seconds: default_webhook_retry_backoff_seconds(),
+            max_per_hour: default_webhook_max_per_hour(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookTemplate {
+    #[default]
+    Generic,  // The event details as JSON, plus a human readable message
+    Slack,    // Slack incoming webhook ({"text": ...})
+    Teams,    // Microsoft Teams incoming webhook (MessageCard)
+}
+
+/// Thresholds for marking servers down and up across daemon rounds
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct HealthConfig {
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    #[serde(default = "default_recovery_threshold")]
+    pub recovery_threshold: u32,
+    #[serde(default = "default_cooldown_seconds")]
+    pub cooldown_seconds: u64,
+    #[serde(default = "default_max_cooldown_seconds")]
+    pub max_cooldown_seconds: u64,
+    /// Weight of the newest latency sample in the moving average (0 < factor <= 1)
+    #[serde(default = "default_smoothing_factor")]
+    pub smoothing_factor: f64,
+    /// Number of recent probe outcomes kept per server
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+}
+
+fn default_failure_threshold() -> u32 {
+    2
+}
+
+fn default_recovery_threshold() -> u32 {
+    2
+}
+
+fn default_cooldown_seconds() -> u64 {
+    60
+}
+
+fn default_max_cooldown_seconds() -> u64 {
+    1800
+}
+
+fn default_smoothing_factor() -> f64 {
+    0.3
+}
+
+fn default_history_size() -> usize {
+    20
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_failure_threshold(),
+            recovery_threshold: default_recovery_threshold(),
+            cooldown_seconds: default_cooldown_seconds(),
+            max_cooldown_seconds: default_max_cooldown_seconds(),
+            smoothing_factor: default_smoothing_factor(),
+            history_size: default_history_size(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExternalChangePolicy {
+    #[default]
+    Reapply,  // Write the selected servers back immediately
+    Backoff,  // Wait backoff_seconds (doubling on repeated changes) before writing back
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DnsServer {
     pub name: String,
     pub address: IpAddr,
+    /// Who operates the server, e.g. "cloudflare". Well-known public resolvers
+    /// are recognized without it
+    #[serde(default, alias = "group")]
+    pub provider: Option<String>,
+    /// FirstOnline mode: servers with a lower priority are used first, the list
+    /// order breaks ties. Hybrid mode: servers of the same priority form a tier
+    #[serde(default, alias = "tier")]
+    pub priority: u32,
+    /// Benchmark and hybrid modes: added to the measured latency when ranking, negative to
+    /// favour the server, positive to penalize it
+    #[serde(default)]
+    pub latency_bias_ms: f64,
+    /// Benchmark and hybrid modes: the measured latency is divided by it when ranking, so a
+    /// server of weight 2 competes as if it were twice as fast
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+    /// Always written to resolv.conf, before the selected servers, whatever the
+    /// probes say
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+impl DnsServer {
+    pub fn provider(&self) -> Option<&str> {
+        self.provider.as_deref().or_else(|| known_provider(self.address))
+    }
+
+    /// Latency the server is ranked on in benchmark and hybrid modes
+    pub fn score(&self, latency_ms: f64) -> f64 {
+        latency_ms / self.weight + self.latency_bias_ms
+    }
+
+    fn is_scored(&self) -> bool {
+        self.latency_bias_ms != 0.0 || self.weight != 1.0
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SelectionPolicy {
+    #[default]
+    Ranked,   // The best servers, in the order of the mode (list order or latency)
+    Diverse,  // The best server of each provider before a second one of the same provider
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FamilyPolicy {
+    #[default]
+    Any,         // Servers of both families, in the order of the mode
+    Ipv4Only,    // IPv4 servers only
+    Ipv6Only,    // IPv6 servers only
+    PreferIpv6,  // IPv6 servers before IPv4 servers
+    OneOfEach,   // The best server of each family before a second one of the same family
+}
+
+impl FamilyPolicy {
+    pub fn allows(self, address: IpAddr) -> bool {
+        match self {
+            FamilyPolicy::Ipv4Only => address.is_ipv4(),
+            FamilyPolicy::Ipv6Only => address.is_ipv6(),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AllOfflinePolicy {
+    #[default]
+    Keep,      // Leave resolv.conf as it is
+    Fallback,  // Write fallback_servers
+    All,       // Write every configured server, in configuration order
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -29,59 +402,145 @@ pub struct DnsServer {
 pub enum OperationMode {
     FirstOnline,  // Select first N online DNS servers from the list
     Benchmark,    // Benchmark and select best DNS servers by latency
+    Hybrid,       // Benchmark and select the best DNS servers of the most preferred healthy tier
+}
+
+impl AllOfflinePolicy {
+    /// Name of the policy in the configuration
+    pub fn name(&self) -> &'static str {
+        match self {
+            AllOfflinePolicy::Keep => "keep",
+            AllOfflinePolicy::Fallback => "fallback",
+            AllOfflinePolicy::All => "all",
+        }
+    }
+}
+
+impl OperationMode {
+    /// Name of the mode in the configuration
+    pub fn name(&self) -> &'static str {
+        match self {
+            OperationMode::FirstOnline => "firstonline",
+            OperationMode::Benchmark => "benchmark",
+            OperationMode::Hybrid => "hybrid",
+        }
+    }
+}
+
+/// How serious a problem found in the configuration is
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,    // The configuration is rejected
+    Warning,  // Accepted, but probably not what was meant
+}
+
+/// A problem found in the configuration
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Setting the problem is about, e.g. `health.cooldown_seconds`, if any
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn error(field: Option<&str>, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            field: field.map(str::to_string),
+            message,
+        }
+    }
+}
+
+/// Collects the diagnostics of a configuration
+#[derive(Default)]
+struct Lint(Vec<Diagnostic>);
+
+impl Lint {
+    fn error(&mut self, field: &str, message: String) {
+        self.0.push(Diagnostic::error(Some(field), message));
+    }
+
+    fn warning(&mut self, field: &str, message: String) {
+        self.0.push(Diagnostic {
+            severity: Severity::Warning,
+            field: Some(field.to_string()),
+            message,
+        });
+    }
 }
 
 impl Config {
+    /// Load and validate the configuration, failing if it has any error
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let config = Self::parse(path)?;
+        ensure_no_errors(&config.config_diagnostics())?;
+        Ok(config)
+    }
+
+    /// Load the configuration without validating it
+    pub fn parse<P: AsRef<Path>>(path: P) -> Result<Self> {
         let content = std::fs::read_to_string(path)
             .context("Failed to read configuration file")?;
 
         let config: Config = serde_yaml::from_str(&content)
             .context("Failed to parse YAML configuration")?;
 
-        config.validate()?;
-
         Ok(config)
     }
 
-    fn validate(&self) -> Result<()> {
-        // Validate at least 2 DNS servers are configured
-        if self.dns_servers.is_empty() {
-            bail!("At least one DNS server must be configured");
-        }
+    /// Warnings about a configuration that loaded successfully
+    pub fn warnings(&self) -> Vec<Diagnostic> {
+        self.config_diagnostics()
+            .into_iter()
+            .filter(|d| d.severity == Severity::Warning)
+            .collect()
+    }
 
-        if self.dns_servers.len() < 2 {
-            bail!(
-                "At least 2 DNS servers recommended for redundancy (currently {})",
-                self.dns_servers.len()
-            );
-        }
+    /// Every error and warning in the configuration, including the checks against this host
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.config_diagnostics();
+        diagnostics.extend(self.environment_diagnostics());
+        diagnostics
+    }
 
-        // Validate execution interval
-        if self.execution_interval_seconds == 0 {
-            bail!("execution_interval_seconds must be greater than 0");
-        }
+    /// Errors and warnings in the configuration itself
+    fn config_diagnostics(&self) -> Vec<Diagnostic> {
+        let mut lint = Lint::default();
 
-        // Warn if interval is too short
-        if self.execution_interval_seconds < 30 {
-            eprintln!(
-                "⚠ WARNING: execution_interval_seconds is very short ({} seconds). \
-                This may cause excessive DNS queries. Recommended: 120+ seconds",
-                self.execution_interval_seconds
-            );
-        }
+        self.lint_servers(&mut lint);
+        self.lint_intervals(&mut lint);
+        self.lint_paths(&mut lint);
+        self.lint_sections(&mut lint);
 
-        // Validate timeout
-        if self.timeout_seconds == 0 {
-            bail!("timeout_seconds must be greater than 0");
-        }
+        lint.0
+    }
 
-        // Warn if timeout is too long
-        if self.timeout_seconds > 10 {
-            eprintln!(
-                "⚠ WARNING: timeout_seconds is very long ({} seconds). \
-                DNS queries may take too long. Recommended: 2-5 seconds",
-                self.timeout_seconds
+    /// Problems of the configuration on this host: its paths, IPv6 routing and the
+    /// systemd watchdog. Only worth checking before probing or writing resolv.conf
+    pub fn environment_diagnostics(&self) -> Vec<Diagnostic> {
+        let mut lint = Lint::default();
+
+        self.lint_filesystem(&mut lint);
+        self.lint_ipv6_route(&mut lint);
+        self.lint_watchdog(&mut lint);
+
+        lint.0
+    }
+
+    fn lint_servers(&self, lint: &mut Lint) {
+        // Validate at least 2 DNS servers are configured
+        if self.dns_servers.is_empty() {
+            lint.error("dns_servers", "At least one DNS server must be configured".to_string());
+        } else if self.dns_servers.len() < 2 {
+            lint.error(
+                "dns_servers",
+                format!(
+                    "At least 2 DNS servers recommended for redundancy (currently {})",
+                    self.dns_servers.len()
+                ),
             );
         }
 
@@ -96,9 +555,9 @@ impl Config {
         }
 
         if !duplicates.is_empty() {
-            bail!(
-                "Duplicate DNS server addresses found:\n  - {}",
-                duplicates.join("\n  - ")
+            lint.error(
+                "dns_servers",
+                format!("Duplicate DNS server addresses found:\n  - {}", duplicates.join("\n  - ")),
             );
         }
 
@@ -113,55 +572,740 @@ impl Config {
         }
 
         if !duplicate_names.is_empty() {
-            eprintln!(
-                "⚠ WARNING: Duplicate DNS server names found: {}",
-                duplicate_names.join(", ")
+            lint.warning(
+                "dns_servers",
+                format!("Duplicate DNS server names found: {}", duplicate_names.join(", ")),
             );
         }
 
-        // Validate resolv.conf path if specified
-        if let Some(path) = &self.resolv_conf_path {
-            if path.is_empty() {
-                bail!("resolv_conf_path cannot be empty");
+        let allowed = self
+            .dns_servers
+            .iter()
+            .filter(|server| self.family_policy.allows(server.address))
+            .count();
+
+        if !self.dns_servers.is_empty() && allowed == 0 {
+            lint.error(
+                "family_policy",
+                "family_policy excludes every configured DNS server".to_string(),
+            );
+        }
+
+        if self.family_policy == FamilyPolicy::OneOfEach
+            && (!self.dns_servers.iter().any(|server| server.address.is_ipv4())
+                || !self.dns_servers.iter().any(|server| server.address.is_ipv6()))
+        {
+            lint.warning(
+                "family_policy",
+                "family_policy one_of_each needs both IPv4 and IPv6 servers".to_string(),
+            );
+        }
+
+        for server in &self.dns_servers {
+            if !(server.weight.is_finite() && server.weight > 0.0) {
+                lint.error(
+                    "dns_servers",
+                    format!("weight of {} must be a number greater than 0", server.name),
+                );
+            }
+            if !server.latency_bias_ms.is_finite() {
+                lint.error(
+                    "dns_servers",
+                    format!("latency_bias_ms of {} must be a number", server.name),
+                );
             }
+        }
 
-            // Check if parent directory exists
-            if let Some(parent) = Path::new(path).parent() {
-                if !parent.exists() {
-                    bail!(
-                        "Parent directory does not exist for resolv_conf_path: {}",
-                        parent.display()
-                    );
+        let pinned = self.pinned_servers().len();
+        if pinned >= MAX_NAMESERVERS {
+            lint.error(
+                "dns_servers",
+                format!(
+                    "{} servers are pinned, but the resolver only uses {} nameservers: \
+                    at most {} can be pinned to leave room for the selection",
+                    pinned,
+                    MAX_NAMESERVERS,
+                    MAX_NAMESERVERS - 1
+                ),
+            );
+        } else if pinned > 0 && self.dns_servers.len() - pinned < 2 {
+            lint.warning(
+                "dns_servers",
+                "Fewer than 2 servers are left for the selection besides the pinned servers".to_string(),
+            );
+        }
+
+        match self.on_all_offline {
+            AllOfflinePolicy::Fallback if self.fallback_servers.is_empty() => lint.error(
+                "fallback_servers",
+                "on_all_offline is fallback, but no fallback_servers are configured".to_string(),
+            ),
+            AllOfflinePolicy::Keep | AllOfflinePolicy::All if !self.fallback_servers.is_empty() => lint.warning(
+                "fallback_servers",
+                "fallback_servers are only used with on_all_offline: fallback".to_string(),
+            ),
+            _ => {}
+        }
+
+        // Settings of the other mode are silently ignored
+        let ignored: Vec<_> = self
+            .dns_servers
+            .iter()
+            .filter(|server| match self.mode {
+                OperationMode::FirstOnline => server.is_scored(),
+                OperationMode::Benchmark => server.priority != 0,
+                OperationMode::Hybrid => false,
+            })
+            .map(|server| server.name.as_str())
+            .collect();
+
+        if !ignored.is_empty() {
+            let settings = match self.mode {
+                OperationMode::FirstOnline => {
+                    "latency_bias_ms and weight are only used in benchmark and hybrid modes"
                 }
+                _ => "priority is only used in firstonline and hybrid modes",
+            };
+            lint.warning("dns_servers", format!("{}: {}", settings, ignored.join(", ")));
+        }
+
+        if self
+            .dns_servers
+            .iter()
+            .any(|server| server.provider.as_deref().is_some_and(|p| p.trim().is_empty()))
+        {
+            lint.error("dns_servers", "provider cannot be empty (leave it out instead)".to_string());
+        }
+
+        // One provider outage should not take down every server
+        let providers: HashSet<_> = self
+            .dns_servers
+            .iter()
+            .map(|server| server.provider().map(str::to_lowercase))
+            .collect();
+
+        if self.dns_servers.len() >= 2 && providers.len() == 1 {
+            if let Some(provider) = self.dns_servers[0].provider() {
+                lint.warning(
+                    "dns_servers",
+                    format!(
+                        "All DNS servers belong to {}: an outage of that provider leaves no working server",
+                        provider
+                    ),
+                );
             }
         }
+    }
+
+    /// Worst-case duration of a round or health check of the daemon: the connectivity
+    /// precheck, then one timeout for the probes, which run in parallel, and one more
+    /// for the replacements a health check probes
+    fn longest_run_seconds(&self) -> u64 {
+        let precheck = if self.connectivity.enabled && !self.connectivity.targets.is_empty() {
+            self.connectivity.timeout_seconds
+        } else {
+            0
+        };
+        let probes = match (&self.mode, self.health_check_interval_seconds) {
+            (OperationMode::Benchmark | OperationMode::Hybrid, Some(_)) => 2,
+            _ => 1,
+        };
+        precheck + probes * self.timeout_seconds
+    }
+
+    fn lint_intervals(&self, lint: &mut Lint) {
+        // Validate execution interval
+        if self.execution_interval_seconds == 0 {
+            lint.error(
+                "execution_interval_seconds",
+                "execution_interval_seconds must be greater than 0".to_string(),
+            );
+        } else if self.execution_interval_seconds < 30 {
+            // Warn if interval is too short
+            lint.warning(
+                "execution_interval_seconds",
+                format!(
+                    "execution_interval_seconds is very short ({} seconds). \
+                    This may cause excessive DNS queries. Recommended: 120+ seconds",
+                    self.execution_interval_seconds
+                ),
+            );
+        }
+
+        for (field, seconds) in [
+            ("execution_interval_seconds", Some(self.execution_interval_seconds)),
+            ("health_check_interval_seconds", self.health_check_interval_seconds),
+            ("health.cooldown_seconds", Some(self.health.cooldown_seconds)),
+            ("health.max_cooldown_seconds", Some(self.health.max_cooldown_seconds)),
+            ("watch.backoff_seconds", Some(self.watch.backoff_seconds)),
+        ] {
+            if seconds.is_some_and(|seconds| seconds > MAX_INTERVAL_SECONDS) {
+                lint.error(
+                    field,
+                    format!("{} must be at most {} seconds (a year)", field, MAX_INTERVAL_SECONDS),
+                );
+            }
+        }
+
+        if let Some(health_interval) = self.health_check_interval_seconds {
+            if health_interval == 0 {
+                lint.error(
+                    "health_check_interval_seconds",
+                    "health_check_interval_seconds must be greater than 0".to_string(),
+                );
+            } else if health_interval >= self.execution_interval_seconds {
+                lint.warning(
+                    "health_check_interval_seconds",
+                    format!(
+                        "health_check_interval_seconds ({}) is not shorter than \
+                        execution_interval_seconds ({}). Fast failover will have no effect",
+                        health_interval, self.execution_interval_seconds
+                    ),
+                );
+            }
+        }
+
+        // Validate timeout
+        if self.timeout_seconds == 0 {
+            lint.error("timeout_seconds", "timeout_seconds must be greater than 0".to_string());
+        }
+
+        // Warn if timeout is too long
+        if self.timeout_seconds > 10 {
+            lint.warning(
+                "timeout_seconds",
+                format!(
+                    "timeout_seconds is very long ({} seconds). \
+                    DNS queries may take too long. Recommended: 2-5 seconds",
+                    self.timeout_seconds
+                ),
+            );
+        }
 
         // Validate mode-specific settings
         match self.mode {
             OperationMode::FirstOnline => {
+                if self.health_check_interval_seconds.is_some() {
+                    lint.warning(
+                        "health_check_interval_seconds",
+                        "health_check_interval_seconds is ignored in firstonline mode, \
+                        every run is already a health check"
+                            .to_string(),
+                    );
+                }
+
                 if self.execution_interval_seconds > 300 {
-                    eprintln!(
-                        "⚠ WARNING: In firstonline mode, long intervals ({} seconds) \
-                        may delay detection of DNS failures. Recommended: 120 seconds",
-                        self.execution_interval_seconds
+                    lint.warning(
+                        "execution_interval_seconds",
+                        format!(
+                            "In firstonline mode, long intervals ({} seconds) \
+                            may delay detection of DNS failures. Recommended: 120 seconds",
+                            self.execution_interval_seconds
+                        ),
                     );
                 }
             }
-            OperationMode::Benchmark => {
-                if self.execution_interval_seconds < 300 {
-                    eprintln!(
-                        "⚠ WARNING: In benchmark mode, short intervals ({} seconds) \
-                        may cause excessive DNS load. Recommended: 1800 seconds",
-                        self.execution_interval_seconds
+            OperationMode::Benchmark | OperationMode::Hybrid => {
+                if self.execution_interval_seconds > 0 && self.execution_interval_seconds < 300 {
+                    lint.warning(
+                        "execution_interval_seconds",
+                        format!(
+                            "In {} mode, short intervals ({} seconds) \
+                            may cause excessive DNS load. Recommended: 1800 seconds",
+                            self.mode.name(),
+                            self.execution_interval_seconds
+                        ),
                     );
                 }
             }
         }
 
-        Ok(())
+        // A single tier is plain benchmark mode
+        if matches!(self.mode, OperationMode::Hybrid) {
+            let tiers: HashSet<_> = self.dns_servers.iter().map(|server| server.priority).collect();
+            if self.dns_servers.len() >= 2 && tiers.len() == 1 {
+                lint.warning(
+                    "dns_servers",
+                    "In hybrid mode, servers are grouped into tiers by priority, \
+                    but all servers have the same priority"
+                        .to_string(),
+                );
+            }
+        }
+    }
+
+    fn lint_paths(&self, lint: &mut Lint) {
+        if self.resolv_conf_path.as_deref() == Some("") {
+            lint.error("resolv_conf_path", "resolv_conf_path cannot be empty".to_string());
+        }
+
+        for (field, path) in [
+            ("state_file", &self.state_file),
+            ("control_socket", &self.control_socket),
+        ] {
+            if path.as_deref() == Some("") {
+                lint.error(field, format!("{} cannot be empty (use null to disable it)", field));
+            }
+        }
+    }
+
+    /// Check that the files autodns writes can be written
+    fn lint_filesystem(&self, lint: &mut Lint) {
+        // Validate resolv.conf path if specified
+        if self.resolv_conf_path.as_deref() != Some("") {
+            let path = Path::new(self.resolv_conf_path());
+            match path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                // systemd creates the runtime directory of the generated unit
+                Some(parent) if !parent.exists() && parent == Path::new(systemd::RUNTIME_DIR) => {}
+                // Check if parent directory exists
+                Some(parent) if !parent.exists() => lint.error(
+                    "resolv_conf_path",
+                    format!(
+                        "Parent directory does not exist for resolv_conf_path: {}",
+                        parent.display()
+                    ),
+                ),
+                Some(parent) if !parent.is_dir() => lint.error(
+                    "resolv_conf_path",
+                    format!("{} is not a directory", parent.display()),
+                ),
+                parent => {
+                    if path.is_dir() {
+                        lint.error(
+                            "resolv_conf_path",
+                            format!("resolv_conf_path is a directory: {}", path.display()),
+                        );
+                    } else if path.is_symlink() {
+                        lint.warning(
+                            "resolv_conf_path",
+                            format!(
+                                "{} is a symlink, probably managed by another tool \
+                                (e.g. systemd-resolved). It will be replaced by a regular file",
+                                path.display()
+                            ),
+                        );
+                    }
+
+                    // The file is replaced by renaming a temporary file next to it,
+                    // or else written in place
+                    let parent = parent.unwrap_or(Path::new("."));
+                    let writable = is_writable(parent) || (path.exists() && is_writable(path));
+                    if !writable {
+                        lint.warning(
+                            "resolv_conf_path",
+                            format!(
+                                "{} is not writable by the current user, run autodns as root",
+                                parent.display()
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+
+        for (field, path) in [
+            ("state_file", &self.state_file),
+            ("control_socket", &self.control_socket),
+        ] {
+            let Some(path) = path.as_deref().filter(|path| !path.is_empty()) else {
+                continue;
+            };
+
+            let path = Path::new(path);
+            if path.is_dir() {
+                lint.error(field, format!("{} is a directory: {}", field, path.display()));
+                continue;
+            }
+
+            // Missing directories are created at startup, from the closest existing one
+            let existing = path.ancestors().skip(1).find(|dir| dir.exists());
+            match existing {
+                Some(dir) if !dir.is_dir() => lint.error(
+                    field,
+                    format!("{} cannot be created: {} is not a directory", path.display(), dir.display()),
+                ),
+                Some(dir) if !is_writable(dir) => lint.warning(
+                    field,
+                    format!(
+                        "{} cannot be created: {} is not writable by the current user",
+                        path.display(),
+                        dir.display()
+                    ),
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    /// IPv6 servers are skipped on a host that cannot route IPv6
+    fn lint_ipv6_route(&self, lint: &mut Lint) {
+        let ipv6: Vec<_> = self
+            .dns_servers
+            .iter()
+            .filter(|server| server.address.is_ipv6() && self.family_policy.allows(server.address))
+            .map(|server| server.name.as_str())
+            .collect();
+
+        if !ipv6.is_empty() && !network::has_ipv6_default_route() {
+            lint.warning(
+                "dns_servers",
+                format!(
+                    "This host has no IPv6 default route, so the IPv6 servers will not be used: {}",
+                    ipv6.join(", ")
+                ),
+            );
+        }
+    }
+
+    /// The daemon loop cannot ping the systemd watchdog while a run is in progress
+    fn lint_watchdog(&self, lint: &mut Lint) {
+        let longest_run = self.longest_run_seconds();
+        if longest_run > systemd::WATCHDOG_SEC / 2 {
+            lint.warning(
+                "timeout_seconds",
+                format!(
+                    "A run may take up to {} seconds, more than half of the WatchdogSec={}s \
+                    of the systemd unit, which may restart the daemon. Lower timeout_seconds \
+                    or raise WatchdogSec",
+                    longest_run,
+                    systemd::WATCHDOG_SEC
+                ),
+            );
+        }
+    }
+
+    fn lint_sections(&self, lint: &mut Lint) {
+        if self.watch.enabled && self.watch.backoff_seconds == 0 {
+            lint.error("watch.backoff_seconds", "watch.backoff_seconds must be greater than 0".to_string());
+        }
+
+        if self.connectivity.enabled && self.connectivity.timeout_seconds == 0 {
+            lint.error(
+                "connectivity.timeout_seconds",
+                "connectivity.timeout_seconds must be greater than 0".to_string(),
+            );
+        }
+
+        if self.health.failure_threshold == 0 || self.health.recovery_threshold == 0 {
+            lint.error(
+                "health.failure_threshold",
+                "health.failure_threshold and health.recovery_threshold must be greater than 0".to_string(),
+            );
+        }
+
+        if self.health.cooldown_seconds == 0 {
+            lint.error("health.cooldown_seconds", "health.cooldown_seconds must be greater than 0".to_string());
+        }
+
+        if self.health.max_cooldown_seconds < self.health.cooldown_seconds {
+            lint.error(
+                "health.max_cooldown_seconds",
+                format!(
+                    "health.max_cooldown_seconds ({}) must not be shorter than health.cooldown_seconds ({})",
+                    self.health.max_cooldown_seconds, self.health.cooldown_seconds
+                ),
+            );
+        }
+
+        if !(self.health.smoothing_factor > 0.0 && self.health.smoothing_factor <= 1.0) {
+            lint.error(
+                "health.smoothing_factor",
+                format!(
+                    "health.smoothing_factor must be greater than 0 and at most 1 (currently {})",
+                    self.health.smoothing_factor
+                ),
+            );
+        }
+
+        if self.health.history_size == 0 {
+            lint.error("health.history_size", "health.history_size must be greater than 0".to_string());
+        }
+
+        for (name, command) in [
+            ("on_change", &self.hooks.on_change),
+            ("on_all_offline", &self.hooks.on_all_offline),
+            ("on_recovery", &self.hooks.on_recovery),
+            ("on_write_error", &self.hooks.on_write_error),
+        ] {
+            if command.as_deref().is_some_and(|c| c.trim().is_empty()) {
+                lint.error(
+                    &format!("hooks.{}", name),
+                    format!("hooks.{} cannot be empty (use null to disable it)", name),
+                );
+            }
+        }
+
+        if self.hooks.timeout_seconds == 0 {
+            lint.error("hooks.timeout_seconds", "hooks.timeout_seconds must be greater than 0".to_string());
+        }
+
+        if self.webhook.enabled {
+            match self.webhook.url.as_deref() {
+                Some(url) if url.starts_with("http://") || url.starts_with("https://") => {}
+                Some(url) => lint.error(
+                    "webhook.url",
+                    format!("webhook.url must be an http:// or https:// URL (currently {})", url),
+                ),
+                None => lint.error(
+                    "webhook.url",
+                    "webhook.url is required when the webhook is enabled".to_string(),
+                ),
+            }
+
+            if self.webhook.timeout_seconds == 0 || self.webhook.retry_backoff_seconds == 0 {
+                lint.error(
+                    "webhook.timeout_seconds",
+                    "webhook.timeout_seconds and webhook.retry_backoff_seconds must be greater than 0"
+                        .to_string(),
+                );
+            }
+
+            if self.webhook.max_per_hour == 0 {
+                lint.error("webhook.max_per_hour", "webhook.max_per_hour must be greater than 0".to_string());
+            }
+        }
     }
 
     pub fn resolv_conf_path(&self) -> &str {
         self.resolv_conf_path.as_deref().unwrap_or("/etc/resolv.conf")
     }
+
+    /// Servers to write when every server is offline, if any
+    pub fn all_offline_servers(&self) -> Option<Vec<IpAddr>> {
+        match self.on_all_offline {
+            AllOfflinePolicy::Keep => None,
+            AllOfflinePolicy::Fallback => Some(self.fallback_servers.clone()),
+            AllOfflinePolicy::All => Some(self.dns_servers.iter().map(|server| server.address).collect()),
+        }
+        .filter(|servers| !servers.is_empty())
+    }
+
+    /// Servers always written to resolv.conf, in configuration order
+    pub fn pinned_servers(&self) -> Vec<IpAddr> {
+        self.dns_servers
+            .iter()
+            .filter(|server| server.pinned)
+            .map(|server| server.address)
+            .collect()
+    }
+
+    /// How many servers to select: 2, unless the pinned servers leave fewer of
+    /// the nameserver slots the resolver uses
+    pub fn selection_size(&self) -> usize {
+        let pinned = self.dns_servers.iter().filter(|server| server.pinned).count();
+        MAX_NAMESERVERS.saturating_sub(pinned).min(2)
+    }
+
+    /// Pick `count` servers from a ranking, best first, following the selection policy
+    pub fn select(&self, ranked: &[IpAddr], count: usize) -> Vec<IpAddr> {
+        self.extend_selection(Vec::new(), ranked, count)
+    }
+
+    /// Fill up a selection that already starts with some servers, such as the
+    /// operator's pin, which stay first whatever the policies
+    pub fn extend_selection(
+        &self,
+        mut selected: Vec<IpAddr>,
+        ranked: &[IpAddr],
+        count: usize,
+    ) -> Vec<IpAddr> {
+        while selected.len() < count {
+            match self.candidates(&selected, ranked).first() {
+                Some(address) => selected.push(*address),
+                None => break,
+            }
+        }
+        selected
+    }
+
+    /// Servers of the ranking that could join the selection, best first, leaving out
+    /// the families the family policy excludes. The family policy may move servers of
+    /// a family up; then, with the diverse policy, servers of providers missing from
+    /// the selection come first.
+    pub fn candidates(&self, selected: &[IpAddr], ranked: &[IpAddr]) -> Vec<IpAddr> {
+        let mut candidates: Vec<_> = ranked
+            .iter()
+            .copied()
+            .filter(|address| {
+                !selected.contains(address)
+                    && self.family_policy.allows(*address)
+                    && !self.is_pinned(*address)
+            })
+            .collect();
+
+        let families: HashSet<_> = selected.iter().map(IpAddr::is_ipv6).collect();
+        let used: HashSet<_> = match self.selection_policy {
+            SelectionPolicy::Diverse => selected.iter().map(|address| self.group(*address)).collect(),
+            SelectionPolicy::Ranked => HashSet::new(),
+        };
+
+        // Stable, so the ranking is kept among equal servers
+        candidates.sort_by_key(|address| {
+            let family = match self.family_policy {
+                FamilyPolicy::PreferIpv6 => address.is_ipv4(),
+                FamilyPolicy::OneOfEach => families.contains(&address.is_ipv6()),
+                _ => false,
+            };
+            (family, used.contains(&self.group(*address)))
+        });
+
+        candidates
+    }
+
+    fn is_pinned(&self, address: IpAddr) -> bool {
+        self.dns_servers
+            .iter()
+            .any(|server| server.pinned && server.address == address)
+    }
+
+    /// Whether a server is worth probing: its family is allowed, and reachable
+    /// from this host
+    pub fn is_probed(&self, address: IpAddr, ipv6_available: bool) -> bool {
+        self.family_policy.allows(address) && (address.is_ipv4() || ipv6_available)
+    }
+
+    /// Provider of a server for the diverse policy; a server without one is a group of its own
+    fn group(&self, address: IpAddr) -> String {
+        self.dns_servers
+            .iter()
+            .find(|server| server.address == address)
+            .and_then(|server| server.provider())
+            .map(str::to_lowercase)
+            .unwrap_or_else(|| address.to_string())
+    }
+}
+
+/// Whether the current user may create files in the directory
+fn is_writable(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: path is a valid NUL terminated string for the duration of the call
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// Fail with every error among the diagnostics
+pub fn ensure_no_errors(diagnostics: &[Diagnostic]) -> Result<()> {
+    let errors: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .map(|d| d.message.as_str())
+        .collect();
+
+    match errors.as_slice() {
+        [] => Ok(()),
+        [error] => bail!("{}", error),
+        errors => bail!("Invalid configuration:\n  - {}", errors.join("\n  - ")),
+    }
+}
+
+/// Public resolver operator of a well-known anycast address
+fn known_provider(address: IpAddr) -> Option<&'static str> {
+    match address {
+        IpAddr::V4(v4) => match v4.octets() {
+            [1, 1, 1, _] | [1, 0, 0, _] => Some("Cloudflare"),
+            [8, 8, 8, 8] | [8, 8, 4, 4] => Some("Google"),
+            [9, 9, 9, _] | [149, 112, 112, _] => Some("Quad9"),
+            [208, 67, 222, _] | [208, 67, 220, _] => Some("OpenDNS"),
+            [94, 140, 14, _] | [94, 140, 15, _] => Some("AdGuard"),
+            [185, 12, 64, _] => Some("Hetzner"),
+            _ => None,
+        },
+        IpAddr::V6(v6) => match v6.segments() {
+            [0x2606, 0x4700, 0x4700, ..] => Some("Cloudflare"),
+            [0x2001, 0x4860, 0x4860, ..] => Some("Google"),
+            [0x2620, 0xfe, ..] => Some("Quad9"),
+            [0x2620, 0x119, ..] => Some("OpenDNS"),
+            [0x2a10, 0x50c0, ..] => Some("AdGuard"),
+            [0x2a01, 0x4ff, 0xff00, ..] => Some("Hetzner"),
+            _ => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_checker::test_support::addresses;
+
+    fn config(yaml: &str) -> Config {
+        let base = "execution_interval_seconds: 120\ntimeout_seconds: 1\nmode: firstonline\n";
+        serde_yaml::from_str(&format!("{}{}", base, yaml)).unwrap()
+    }
+
+    #[test]
+    fn test_diverse_policy_picks_other_providers_first() {
+        let config = config(
+            "selection_policy: diverse
+dns_servers:
+  - { name: Cloudflare-1, address: 1.1.1.1 }
+  - { name: Cloudflare-2, address: 1.0.0.1 }
+  - { name: Lab, address: 192.0.2.1, provider: lab }
+",
+        );
+        let ranked = addresses(&["1.1.1.1", "1.0.0.1", "192.0.2.1"]);
+
+        assert_eq!(config.select(&ranked, 2), addresses(&["1.1.1.1", "192.0.2.1"]));
+        // Servers of a used provider still fill the selection
+        assert_eq!(config.select(&ranked, 3), addresses(&["1.1.1.1", "192.0.2.1", "1.0.0.1"]));
+    }
+
+    #[test]
+    fn test_operator_pin_stays_first_under_family_policy() {
+        let servers = "dns_servers:
+  - { name: Lab-4, address: 192.0.2.1 }
+  - { name: Lab-6a, address: '2001:db8::1' }
+  - { name: Lab-6b, address: '2001:db8::2' }
+";
+        let ranked = addresses(&["2001:db8::1", "192.0.2.1", "2001:db8::2"]);
+        let pin = addresses(&["192.0.2.1"]);
+
+        let prefer_ipv6 = config(&format!("family_policy: prefer_ipv6\n{}", servers));
+        assert_eq!(
+            prefer_ipv6.extend_selection(pin.clone(), &ranked, 2),
+            addresses(&["192.0.2.1", "2001:db8::1"])
+        );
+
+        let ipv6_only = config(&format!("family_policy: ipv6_only\n{}", servers));
+        assert_eq!(
+            ipv6_only.extend_selection(pin, &ranked, 2),
+            addresses(&["192.0.2.1", "2001:db8::1"])
+        );
+    }
+
+    #[test]
+    fn test_ranked_policy_keeps_ranking() {
+        let config = config(
+            "dns_servers:
+  - { name: Cloudflare-1, address: 1.1.1.1 }
+  - { name: Cloudflare-2, address: 1.0.0.1 }
+  - { name: Lab, address: 192.0.2.1, provider: lab }
+",
+        );
+        let ranked = addresses(&["1.1.1.1", "1.0.0.1", "192.0.2.1"]);
+
+        assert_eq!(config.select(&ranked, 2), addresses(&["1.1.1.1", "1.0.0.1"]));
+    }
+
+    #[test]
+    fn test_intervals_off_the_clock_are_errors() {
+        let config = config(
+            "health_check_interval_seconds: 18446744073709551615
+health:
+  max_cooldown_seconds: 18446744073709551615
+dns_servers:
+  - { name: Lab, address: 192.0.2.1 }
+",
+        );
+        let errors: Vec<_> = config
+            .diagnostics()
+            .into_iter()
+            .filter(|d| d.severity == Severity::Error)
+            .filter_map(|d| d.field)
+            .collect();
+
+        assert!(errors.contains(&"health_check_interval_seconds".to_string()));
+        assert!(errors.contains(&"health.max_cooldown_seconds".to_string()));
+        assert!(!errors.contains(&"execution_interval_seconds".to_string()));
+    }
 }
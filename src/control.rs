@@ -0,0 +1,165 @@
+use crate::status::{DaemonStatus, SharedStatus};
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+
+/// Longest timed exclusion, a year; longer ones are refused
+pub const MAX_EXCLUDE_SECONDS: u64 = 366 * 24 * 60 * 60;
+
+/// A request sent to the daemon, one JSON object per line
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Request {
+    Status,
+    /// Run a full round now
+    Trigger,
+    /// Stop touching resolv.conf until resumed
+    Pause,
+    Resume,
+    /// Always keep this server first in resolv.conf
+    Pin { server: String },
+    Unpin,
+    /// Keep this server out of the selection, optionally for a limited time
+    Exclude {
+        server: String,
+        duration_seconds: Option<u64>,
+    },
+    Include { server: String },
+}
+
+/// The daemon's answer to a request, one JSON object per line
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "lowercase")]
+pub enum Response {
+    Status(Box<DaemonStatus>),
+    Ok { message: String },
+    Error { message: String },
+}
+
+/// A request that has to be handled by the daemon loop itself
+pub struct ControlCommand {
+    pub request: Request,
+    pub reply: oneshot::Sender<Response>,
+}
+
+/// Fail if a daemon already answers on the control socket
+pub fn ensure_not_running(path: &str) -> Result<()> {
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        bail!("Another autodns daemon is already running, it answers on {}", path);
+    }
+    Ok(())
+}
+
+/// Bind the control socket, replacing a stale socket left by a previous run
+pub fn bind(path: &str) -> Result<UnixListener> {
+    let socket_path = Path::new(path);
+
+    if let Some(parent) = socket_path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+    }
+
+    if let Ok(metadata) = fs::symlink_metadata(socket_path) {
+        if !metadata.file_type().is_socket() {
+            bail!("{} exists and is not a socket", path);
+        }
+        // Only a socket nobody answers on is stale
+        ensure_not_running(path)?;
+        fs::remove_file(socket_path)
+            .with_context(|| format!("Failed to remove stale socket {}", path))?;
+    }
+
+    let listener = UnixListener::bind(socket_path)
+        .with_context(|| format!("Failed to bind control socket {}", path))?;
+
+    // Only root may query or control the daemon
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to set permissions on {}", path))?;
+
+    info!("Listening for control requests on {}", path);
+    Ok(listener)
+}
+
+/// Answer control requests until the daemon exits. Status is answered from the
+/// shared snapshot; every other request is passed on to the daemon loop.
+pub async fn serve(listener: UnixListener, status: SharedStatus, commands: mpsc::Sender<ControlCommand>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let status = status.clone();
+                let commands = commands.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, status, commands).await {
+                        debug!("Control request failed: {:#}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Failed to accept control connection: {}", e),
+        }
+    }
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    status: SharedStatus,
+    commands: mpsc::Sender<ControlCommand>,
+) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Status) => match status.lock() {
+                Ok(status) => Response::Status(Box::new(status.snapshot())),
+                Err(_) => Response::Error {
+                    message: "status unavailable".to_string(),
+                },
+            },
+            Ok(request) => {
+                let (reply, response) = oneshot::channel();
+                commands
+                    .send(ControlCommand { request, reply })
+                    .await
+                    .context("The daemon loop is not accepting commands")?;
+                response.await.context("The daemon loop dropped the command")?
+            }
+            Err(e) => Response::Error {
+                message: format!("invalid request: {}", e),
+            },
+        };
+
+        let mut encoded = serde_json::to_string(&response)?;
+        encoded.push('\n');
+        writer.write_all(encoded.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+/// Send a single request to the daemon and wait for its response
+pub async fn send_request(path: &str, request: &Request) -> Result<Response> {
+    let stream = UnixStream::connect(path).await.with_context(|| {
+        format!("Cannot connect to {}. Is the autodns daemon running?", path)
+    })?;
+    let (reader, mut writer) = stream.into_split();
+
+    let mut encoded = serde_json::to_string(request)?;
+    encoded.push('\n');
+    writer.write_all(encoded.as_bytes()).await?;
+
+    let mut lines = BufReader::new(reader).lines();
+    let line = lines
+        .next_line()
+        .await?
+        .context("The daemon closed the connection without answering")?;
+
+    serde_json::from_str(&line).context("Failed to parse the daemon's response")
+}
@@ -0,0 +1,1081 @@
+use crate::config::{Config, ExternalChangePolicy, HooksConfig, OperationMode};
+use crate::control::{self, ControlCommand, Request, Response};
+use crate::dns_checker::{
+    select_best_dns, select_first_online_dns, select_tiered_dns, DnsCheckResult, DnsChecker,
+};
+use crate::health::HealthTracker;
+use crate::hooks::{HookEvent, HookPayload, HookServer, Hooks};
+use crate::logging::LogFormat;
+use crate::metrics::{self, Metrics, SharedMetrics};
+use crate::network::{self, Connectivity};
+use crate::resolv_conf::ResolvConfManager;
+use crate::state::StateStore;
+use crate::status::{unix_now, DaemonStatus, ExcludedServer, SelectedServer, SharedStatus};
+use crate::systemd::{self, Notifier};
+use crate::watcher::ResolvConfWatcher;
+use crate::webhook::Webhook;
+use crate::output::{display_benchmark_results_with_selection, display_check_results};
+use anyhow::Result;
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{self, Instant, Interval};
+
+struct Daemon {
+    config: Config,
+    checker: DnsChecker,
+    resolv_manager: ResolvConfManager,
+    servers: Vec<(IpAddr, String)>,
+    /// Servers most recently written to resolv.conf
+    selection: Vec<IpAddr>,
+    /// Online servers from the last benchmark, fastest first
+    ranking: Vec<IpAddr>,
+    /// Results of the last full round, as the selection saw them
+    last_results: Vec<DnsCheckResult>,
+    /// Operator overrides from the control socket: while paused resolv.conf is
+    /// left alone, a pinned server is always selected first, and excluded servers
+    /// (until the given Unix time, if any) are never selected
+    paused: bool,
+    pinned: Option<IpAddr>,
+    excluded: HashMap<IpAddr, Option<u64>>,
+    health: HealthTracker,
+    state_store: Option<StateStore>,
+    metrics: SharedMetrics,
+    status: SharedStatus,
+    /// Whether the last attempt to save the state failed, to avoid repeating the warning
+    state_save_failed: bool,
+    /// When a backed-off re-apply of the selection is due
+    reapply_at: Option<Instant>,
+    /// External changes seen since the last scheduled round
+    external_streak: u32,
+    hooks: Hooks,
+    webhook: Option<Webhook>,
+    notifier: Option<Notifier>,
+    /// Last STATUS= sent to systemd, to only send changes
+    systemd_status: String,
+    /// Whether the last round found no usable server, so that the all-offline and
+    /// recovery hooks only run on transitions
+    all_offline: bool,
+    /// Whether the host had an IPv6 default route at the start of the last round
+    ipv6_available: bool,
+    /// Whether resolv.conf holds the servers of the all-offline policy
+    fallback: bool,
+    /// Why the host itself is offline, as of the last precheck
+    network_unavailable: Option<String>,
+}
+
+pub async fn run_daemon(mut config: Config, dry_run: bool) -> Result<()> {
+    info!(event = "daemon_started"; "Running in daemon mode");
+
+    // A preview must not disturb a daemon that may already be running, nor
+    // notify anyone of changes that are not made
+    if dry_run {
+        info!("Dry run: resolv.conf is not written; state, control socket, metrics, hooks and webhook are disabled");
+        config.state_file = None;
+        config.control_socket = None;
+        config.metrics.enabled = false;
+        config.hooks = HooksConfig::default();
+        config.webhook.enabled = false;
+    }
+
+    // Two daemons would fight over resolv.conf
+    if let Some(path) = &config.control_socket {
+        control::ensure_not_running(path)?;
+    }
+
+    let resolv_manager = ResolvConfManager::new(config.resolv_conf_path().to_string())
+        .with_dry_run(dry_run)
+        .with_pinned(config.pinned_servers());
+
+    // Check permissions before starting
+    resolv_manager.check_permissions()?;
+
+    let pinned = config.pinned_servers();
+    if !pinned.is_empty() {
+        info!(event = "pinned_servers", pinned:serde = pinned; "Always writing pinned DNS servers first: {:?}", pinned);
+    }
+
+    let checker = DnsChecker::new()
+        .with_timeout(Duration::from_secs(config.timeout_seconds));
+
+    let servers: Vec<_> = config
+        .dns_servers
+        .iter()
+        .map(|s| (s.address, s.name.clone()))
+        .collect();
+
+    let mut watcher = if config.watch.enabled {
+        match ResolvConfWatcher::new(config.resolv_conf_path()) {
+            Ok(watcher) => {
+                info!("Watching {} for external changes", config.resolv_conf_path());
+                Some(watcher)
+            }
+            Err(e) => {
+                warn!("Cannot watch {} for external changes: {:#}", config.resolv_conf_path(), e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let metrics = Metrics::shared();
+    if config.metrics.enabled {
+        let listener = metrics::bind(config.metrics.listen).await?;
+        tokio::spawn(metrics::serve(listener, metrics.clone()));
+    }
+
+    let status = DaemonStatus::shared(config.mode.name().to_string());
+    let (command_sender, mut commands) = mpsc::channel::<ControlCommand>(16);
+    if let Some(path) = &config.control_socket {
+        match control::bind(path) {
+            Ok(listener) => {
+                tokio::spawn(control::serve(listener, status.clone(), command_sender));
+            }
+            Err(e) => warn!("Control socket unavailable, `autodns status` will not work: {:#}", e),
+        }
+    }
+
+    let execution_period = Duration::from_secs(config.execution_interval_seconds);
+    let mut execution_interval = time::interval(execution_period);
+
+    // Fast failover only makes sense when the full round is an expensive benchmark
+    let mut health_interval = match (&config.mode, config.health_check_interval_seconds) {
+        (OperationMode::Benchmark | OperationMode::Hybrid, Some(seconds)) => {
+            info!("Health checking selected DNS servers every {} seconds", seconds);
+            let period = Duration::from_secs(seconds);
+            Some(time::interval_at(Instant::now() + period, period))
+        }
+        _ => None,
+    };
+
+    // Start from the health history of the previous run, if any
+    let mut health = HealthTracker::new(config.health.clone());
+    let mut selection = Vec::new();
+    let mut fallback = false;
+    let state_store = config.state_file.clone().map(StateStore::new);
+    let max_cooldown = Duration::from_secs(config.health.max_cooldown_seconds);
+    if let Some(restored) = state_store.as_ref().and_then(|store| store.load(max_cooldown)) {
+        health.restore(restored.servers, &servers);
+        if restored.fallback {
+            // A fallback is only kept while the all-offline policy still writes it
+            if config.all_offline_servers().as_ref() == Some(&restored.selection) {
+                selection = restored.selection;
+                fallback = true;
+            }
+        } else {
+            // The configuration may have changed since the state was saved
+            selection = restored
+                .selection
+                .into_iter()
+                .filter(|address| servers.iter().any(|(a, _)| a == address))
+                .collect();
+        }
+    }
+
+    let hooks = Hooks::new(config.hooks.clone(), status.clone());
+    let webhook = Webhook::spawn(config.webhook.clone(), status.clone())?;
+
+    let mut daemon = Daemon {
+        config,
+        checker,
+        resolv_manager,
+        servers,
+        selection,
+        ranking: Vec::new(),
+        last_results: Vec::new(),
+        paused: false,
+        pinned: None,
+        excluded: HashMap::new(),
+        health,
+        state_store,
+        metrics,
+        status,
+        state_save_failed: false,
+        reapply_at: None,
+        external_streak: 0,
+        hooks,
+        webhook,
+        notifier: Notifier::from_env(),
+        systemd_status: String::new(),
+        all_offline: false,
+        ipv6_available: network::has_ipv6_default_route(),
+        fallback,
+        network_unavailable: None,
+    };
+
+    // Ping from the loop itself, so that systemd restarts us if it gets stuck
+    let mut watchdog = match (&daemon.notifier, systemd::watchdog_interval()) {
+        (Some(_), Some(period)) => {
+            info!("Pinging the systemd watchdog every {} seconds", period.as_secs_f64());
+            Some(time::interval(period))
+        }
+        _ => None,
+    };
+
+    if !daemon.ipv6_available && daemon.servers.iter().any(|(address, _)| address.is_ipv6()) {
+        info!(event = "ipv6_unavailable"; "No IPv6 default route, skipping the IPv6 DNS servers");
+    }
+
+    // Run initial check/benchmark based on mode
+    daemon.run_round(true).await;
+    daemon.save_state();
+
+    // resolv.conf has been written: units ordered after us can start
+    daemon.systemd_status = daemon.describe_state();
+    if let Some(notifier) = &daemon.notifier {
+        notifier.ready(&daemon.systemd_status);
+    }
+
+    loop {
+        tokio::select! {
+            _ = execution_interval.tick() => {
+                daemon.external_streak = 0;
+                daemon.run_round(false).await;
+                daemon.save_state();
+            }
+            _ = tick(&mut health_interval) => {
+                daemon.run_health_check().await;
+                daemon.save_state();
+            }
+            changed = wait_for_change(&mut watcher) => {
+                match changed {
+                    Ok(()) => daemon.handle_external_change(),
+                    Err(e) => {
+                        daemon.report_error("watch_failed", format!(
+                            "Stopped watching {}: {:#}",
+                            daemon.config.resolv_conf_path(),
+                            e
+                        ));
+                        watcher = None;
+                    }
+                }
+            }
+            Some(command) = commands.recv() => {
+                // A round run on demand restarts the interval, as `status` reports
+                let on_demand = matches!(command.request, Request::Trigger | Request::Resume);
+                let started = Instant::now();
+                let response = daemon.handle_command(command.request).await;
+                let _ = command.reply.send(response);
+                if on_demand {
+                    execution_interval.reset_at(started + execution_period);
+                }
+            }
+            _ = wait_until(daemon.reapply_at) => {
+                daemon.reapply_at = None;
+                info!(event = "backoff_expired"; "Backoff expired, re-applying selected DNS servers");
+                daemon.reapply_selection();
+            }
+            _ = tick(&mut watchdog) => {
+                if let Some(notifier) = &daemon.notifier {
+                    notifier.watchdog();
+                }
+            }
+        }
+
+        daemon.publish_systemd_status();
+    }
+}
+
+impl Daemon {
+    async fn run_round(&mut self, initial: bool) {
+        let started_at = unix_now();
+        self.update_status(|s| {
+            s.last_round_at = Some(started_at);
+            s.next_round_at = Some(started_at.saturating_add(self.config.execution_interval_seconds));
+        });
+        self.expire_exclusions();
+        if !self.check_connectivity().await {
+            self.update_metrics(|m| m.record_skipped_round());
+            return;
+        }
+        self.detect_ipv6();
+
+        match self.config.mode {
+            OperationMode::FirstOnline => {
+                if initial {
+                    info!(event = "round_started"; "Running initial health check (FirstOnline mode)");
+                } else {
+                    info!(event = "round_started"; "Running scheduled health check (FirstOnline mode)");
+                }
+                let targets = self.probe_targets();
+                let results = self.checker.check_multiple(&targets).await;
+                let results = self.health.record_round(&self.probed_servers(), &results, Instant::now());
+                self.publish_health();
+                if self.print_tables() {
+                    display_check_results(&results);
+                }
+
+                // Update resolv.conf with first 2 online servers
+                let selected_dns = self.select_servers(&results);
+                self.last_results = results;
+                self.track_recovery(&selected_dns);
+                if !selected_dns.is_empty() {
+                    if self.apply_selection(selected_dns) {
+                        info!(event = "selection_updated", selection:serde = self.selection;
+                            "Updated resolv.conf with first {} online DNS servers", self.selection.len());
+                        self.update_metrics(|m| m.record_successful_round());
+                    }
+                } else {
+                    if initial {
+                        warn!(event = "all_offline"; "No online DNS servers found!");
+                    } else {
+                        self.report_error("all_offline", "ALERT: All DNS servers are offline!".to_string());
+                    }
+                    self.apply_all_offline_policy();
+                }
+            }
+            OperationMode::Benchmark | OperationMode::Hybrid => {
+                if initial {
+                    info!(event = "round_started"; "Running initial benchmark");
+                } else {
+                    info!(event = "round_started"; "Running scheduled benchmark ({:?} mode)", self.config.mode);
+                }
+                let targets = self.probe_targets();
+                let results = self.checker.benchmark_multiple(&targets).await;
+                self.update_metrics(|m| {
+                    for result in &results {
+                        if let Some(latency) = result.latency_ms {
+                            m.observe_latency(result.address, &result.name, latency);
+                        }
+                    }
+                });
+                let results = self.health.record_round(&self.probed_servers(), &results, Instant::now());
+                self.publish_health();
+
+                // Remember the full ranking so health checks can fail over without a benchmark
+                self.ranking = self.rank(&results);
+
+                // Update resolv.conf with best servers by smoothed latency
+                let best_dns = self.select_servers(&results);
+                if self.print_tables() {
+                    display_benchmark_results_with_selection(&results, &best_dns);
+                }
+                self.last_results = results;
+                self.track_recovery(&best_dns);
+
+                if !best_dns.is_empty() {
+                    if self.apply_selection(best_dns) {
+                        info!(event = "selection_updated", selection:serde = self.selection;
+                            "Updated resolv.conf with {} fastest DNS servers", self.selection.len());
+                        self.update_metrics(|m| m.record_successful_round());
+                    }
+                } else {
+                    if initial {
+                        warn!(event = "all_offline"; "No online DNS servers found!");
+                    } else {
+                        warn!(event = "all_offline"; "No online DNS servers found in benchmark!");
+                    }
+                    self.apply_all_offline_policy();
+                }
+            }
+        }
+    }
+
+    /// Probe only the selected servers and replace failed ones with the next best
+    /// servers from the last benchmark
+    async fn run_health_check(&mut self) {
+        // The fallback servers are replaced by the next round that finds a server online
+        if self.selection.is_empty() || self.fallback || !self.check_connectivity().await {
+            return;
+        }
+
+        let selected: Vec<_> = self
+            .selection
+            .iter()
+            .map(|address| (*address, self.server_name(*address).to_string()))
+            .collect();
+
+        info!(event = "health_check_started"; "Running health check on {} selected DNS servers", selected.len());
+        let results = self.checker.check_multiple(&selected).await;
+
+        let now = Instant::now();
+        let mut new_selection = Vec::new();
+        for result in &results {
+            self.health.record(result, now);
+            if self.health.is_up(result.address) || self.pinned == Some(result.address) {
+                new_selection.push(result.address);
+            } else {
+                warn!(event = "selected_server_down", server = result.name.as_str(), address:% = result.address;
+                    "Selected DNS server {} ({}) is down", result.name, result.address);
+            }
+        }
+
+        if new_selection.len() == self.selection.len() {
+            self.publish_health();
+            return;
+        }
+
+        // Promote the next best servers, making sure they still answer. They are
+        // probed together, so failing over takes a single timeout
+        let candidates: Vec<_> = self
+            .config
+            .candidates(&new_selection, &self.ranking)
+            .into_iter()
+            .filter(|candidate| self.health.is_up(*candidate) && !self.is_excluded(*candidate))
+            .map(|candidate| (candidate, self.server_name(candidate).to_string()))
+            .collect();
+        let results = self.checker.check_multiple(&candidates).await;
+
+        let now = Instant::now();
+        for result in &results {
+            self.health.record(result, now);
+            if result.is_online && new_selection.len() < self.selection.len() {
+                info!(event = "server_promoted", server = result.name.as_str(), address:% = result.address;
+                    "Promoting {} ({}) from the last benchmark", result.name, result.address);
+                new_selection.push(result.address);
+            }
+        }
+
+        self.publish_health();
+        self.track_recovery(&new_selection);
+
+        if new_selection.is_empty() {
+            self.report_error(
+                "all_offline",
+                "ALERT: All selected DNS servers failed and no replacement is online!".to_string(),
+            );
+            self.apply_all_offline_policy();
+            return;
+        }
+
+        if self.apply_selection(new_selection) {
+            info!(event = "selection_updated", selection:serde = self.selection;
+                "Failed over to {} DNS servers: {:?}", self.selection.len(), self.selection);
+        }
+    }
+
+    /// Pick the servers to write from a round's results, honouring the operator's
+    /// exclusions and pinned server
+    fn select_servers(&self, results: &[DnsCheckResult]) -> Vec<IpAddr> {
+        let available: Vec<_> = results
+            .iter()
+            .filter(|r| !self.is_excluded(r.address))
+            .cloned()
+            .collect();
+
+        let ranked = self.rank(&available);
+
+        // The pin stays first: the family and provider ordering only applies to the rest
+        let selected = self.pinned.into_iter().collect();
+        self.config.extend_selection(selected, &ranked, self.config.selection_size())
+    }
+
+    /// Order the online servers of a round as the mode prefers them
+    fn rank(&self, results: &[DnsCheckResult]) -> Vec<IpAddr> {
+        let servers = &self.config.dns_servers;
+        match self.config.mode {
+            OperationMode::FirstOnline => select_first_online_dns(results, servers, results.len()),
+            OperationMode::Benchmark => select_best_dns(results, servers, results.len()),
+            OperationMode::Hybrid => select_tiered_dns(results, servers, self.config.selection_size()),
+        }
+    }
+
+    /// Recompute the selection from the last round after an operator override
+    fn reselect(&mut self) {
+        let results: Vec<_> = self
+            .last_results
+            .iter()
+            .map(|r| DnsCheckResult {
+                is_online: self.health.is_up(r.address),
+                ..r.clone()
+            })
+            .collect();
+
+        let selected = self.select_servers(&results);
+        if selected.is_empty() || selected == self.selection {
+            return;
+        }
+
+        if self.apply_selection(selected) {
+            info!(event = "selection_updated", selection:serde = self.selection;
+                "Updated resolv.conf with {} DNS servers: {:?}", self.selection.len(), self.selection);
+        }
+    }
+
+    fn is_excluded(&self, address: IpAddr) -> bool {
+        match self.excluded.get(&address) {
+            Some(Some(until)) => unix_now() < *until,
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    fn expire_exclusions(&mut self) {
+        let now = unix_now();
+        let expired: Vec<IpAddr> = self
+            .excluded
+            .iter()
+            .filter(|(_, until)| until.is_some_and(|until| now >= until))
+            .map(|(address, _)| *address)
+            .collect();
+
+        for address in expired {
+            self.excluded.remove(&address);
+            info!(
+                event = "exclusion_expired", server = self.server_name(address), address:% = address;
+                "Exclusion of DNS server {} ({}) expired",
+                self.server_name(address),
+                address
+            );
+        }
+        self.publish_overrides();
+    }
+
+    /// Find a configured server by name or address
+    fn find_server(&self, server: &str) -> Option<(IpAddr, String)> {
+        self.servers
+            .iter()
+            .find(|(address, name)| name == server || address.to_string() == server)
+            .cloned()
+    }
+
+    /// Handle an action requested through the control socket
+    async fn handle_command(&mut self, request: Request) -> Response {
+        let message = match request {
+            Request::Status => {
+                // Normally answered by the control socket itself
+                return match self.status.lock() {
+                    Ok(status) => Response::Status(Box::new(status.snapshot())),
+                    Err(_) => Response::Error {
+                        message: "status unavailable".to_string(),
+                    },
+                };
+            }
+            Request::Trigger => {
+                info!(event = "control_trigger"; "Round triggered through the control socket");
+                self.run_round(false).await;
+                self.save_state();
+                if let Some(reason) = &self.network_unavailable {
+                    return Response::Error {
+                        message: format!(
+                            "Skipped the round, the network is unavailable ({}). Keeping {}",
+                            reason,
+                            self.describe_selection()
+                        ),
+                    };
+                }
+                if self.paused {
+                    "Ran a round, resolv.conf was left alone because the daemon is paused".to_string()
+                } else {
+                    format!("Ran a round, selected DNS servers: {}", self.describe_selection())
+                }
+            }
+            Request::Pause => {
+                self.paused = true;
+                self.reapply_at = None;
+                info!(event = "control_pause"; "Paused through the control socket: resolv.conf will not be updated until resumed");
+                "Paused, resolv.conf will not be updated until resumed".to_string()
+            }
+            Request::Resume => {
+                self.paused = false;
+                info!(event = "control_resume"; "Resumed through the control socket");
+                self.publish_overrides();
+                self.run_round(false).await;
+                self.save_state();
+                match &self.network_unavailable {
+                    Some(reason) => format!(
+                        "Resumed, but the network is unavailable ({}). Keeping {}",
+                        reason,
+                        self.describe_selection()
+                    ),
+                    None => format!("Resumed, selected DNS servers: {}", self.describe_selection()),
+                }
+            }
+            Request::Pin { server } => {
+                let Some((address, name)) = self.find_server(&server) else {
+                    return unknown_server(&server);
+                };
+                if self.excluded.remove(&address).is_some() {
+                    info!("Removed exclusion of DNS server {} ({}) to pin it", name, address);
+                }
+                self.pinned = Some(address);
+                info!(event = "control_pin", server = name.as_str(), address:% = address;
+                    "Pinned DNS server {} ({}) through the control socket", name, address);
+                if !self.health.is_up(address) {
+                    warn!("Pinned DNS server {} ({}) is not up", name, address);
+                }
+                self.publish_overrides();
+                self.reselect();
+                format!("Pinned {} ({})", name, address)
+            }
+            Request::Unpin => {
+                let Some(address) = self.pinned.take() else {
+                    return Response::Error {
+                        message: "No DNS server is pinned".to_string(),
+                    };
+                };
+                let name = self.server_name(address).to_string();
+                info!(event = "control_unpin", server = name.as_str(), address:% = address;
+                    "Unpinned DNS server {} ({}) through the control socket", name, address);
+                self.publish_overrides();
+                self.reselect();
+                format!("Unpinned {} ({})", name, address)
+            }
+            Request::Exclude {
+                server,
+                duration_seconds,
+            } => {
+                let Some((address, name)) = self.find_server(&server) else {
+                    return unknown_server(&server);
+                };
+                if duration_seconds.is_some_and(|seconds| seconds > control::MAX_EXCLUDE_SECONDS) {
+                    return Response::Error {
+                        message: format!(
+                            "Exclusions last at most {} seconds, exclude without a duration instead",
+                            control::MAX_EXCLUDE_SECONDS
+                        ),
+                    };
+                }
+                if self.pinned == Some(address) {
+                    self.pinned = None;
+                    info!("Unpinned DNS server {} ({}) to exclude it", name, address);
+                }
+                self.excluded
+                    .insert(address, duration_seconds.map(|seconds| unix_now().saturating_add(seconds)));
+                let duration = match duration_seconds {
+                    Some(seconds) => format!("for {} seconds", seconds),
+                    None => "until included again".to_string(),
+                };
+                info!(
+                    event = "control_exclude", server = name.as_str(), address:% = address;
+                    "Excluded DNS server {} ({}) {} through the control socket",
+                    name, address, duration
+                );
+                self.publish_overrides();
+                self.reselect();
+                format!("Excluded {} ({}) {}", name, address, duration)
+            }
+            Request::Include { server } => {
+                let Some((address, name)) = self.find_server(&server) else {
+                    return unknown_server(&server);
+                };
+                if self.excluded.remove(&address).is_none() {
+                    return Response::Error {
+                        message: format!("{} ({}) is not excluded", name, address),
+                    };
+                }
+                info!(event = "control_include", server = name.as_str(), address:% = address;
+                    "Included DNS server {} ({}) again through the control socket", name, address);
+                self.publish_overrides();
+                self.reselect();
+                format!("Included {} ({}) again", name, address)
+            }
+        };
+
+        Response::Ok { message }
+    }
+
+    fn describe_selection(&self) -> String {
+        if self.selection.is_empty() {
+            return "none".to_string();
+        }
+        self.selection
+            .iter()
+            .map(|address| format!("{} ({})", self.server_name(*address), address))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Reflect the operator overrides in the status
+    fn publish_overrides(&self) {
+        let pinned = self.pinned.map(|address| SelectedServer {
+            name: self.server_name(address).to_string(),
+            address,
+        });
+        let mut excluded: Vec<_> = self
+            .excluded
+            .iter()
+            .map(|(address, until)| ExcludedServer {
+                name: self.server_name(*address).to_string(),
+                address: *address,
+                until: *until,
+            })
+            .collect();
+        excluded.sort_by_key(|e| e.address);
+
+        self.update_status(|s| {
+            s.paused = self.paused;
+            s.pinned = pinned;
+            s.excluded = excluded;
+        });
+    }
+
+    fn update_metrics(&self, update: impl FnOnce(&mut Metrics)) {
+        if let Ok(mut metrics) = self.metrics.lock() {
+            update(&mut metrics);
+        }
+    }
+
+    fn update_status(&self, update: impl FnOnce(&mut DaemonStatus)) {
+        if let Ok(mut status) = self.status.lock() {
+            update(&mut status);
+        }
+    }
+
+    /// Log an error and keep it for the status output
+    fn report_error(&self, event: &str, message: String) {
+        error!(event = event, error = message.as_str(); "{}", message);
+        self.update_status(|s| s.record_error(message));
+    }
+
+    /// Export the current health of every server to the metrics and status
+    fn publish_health(&self) {
+        self.update_metrics(|m| {
+            for (address, name) in &self.servers {
+                let loss_ratio = self.health.get(*address).map(|h| h.loss_ratio()).unwrap_or(0.0);
+                m.set_server_health(*address, name, self.health.is_up(*address), loss_ratio);
+            }
+        });
+        self.update_status(|s| s.set_servers(&self.servers, &self.health));
+    }
+
+    fn save_state(&mut self) {
+        let Some(store) = &self.state_store else {
+            return;
+        };
+
+        match store.save(&self.selection, self.fallback, &self.health) {
+            Ok(()) => self.state_save_failed = false,
+            Err(e) if !self.state_save_failed => {
+                warn!(event = "state_save_failed", error:% = format!("{:#}", e); "Failed to save state: {:#}", e);
+                self.state_save_failed = true;
+            }
+            Err(e) => debug!("Failed to save state: {:#}", e),
+        }
+    }
+
+    /// Run the connectivity precheck, returning whether the DNS servers should be
+    /// probed. While the host is isolated, failed probes would only blame the
+    /// servers, so rounds leave the selection and server health alone.
+    async fn check_connectivity(&mut self) -> bool {
+        let servers: Vec<_> = self.config.dns_servers.iter().map(|server| server.address).collect();
+        let unavailable = match network::precheck(&self.config.connectivity, &servers).await {
+            Connectivity::Available => None,
+            Connectivity::NetworkUnavailable(reason) => Some(reason),
+        };
+
+        if unavailable != self.network_unavailable {
+            match &unavailable {
+                Some(reason) => warn!(event = "network_unavailable", reason = reason.as_str();
+                    "Network unavailable ({}), skipping rounds until it is back", reason),
+                None => info!(event = "network_available"; "Network available again, resuming rounds"),
+            }
+            self.network_unavailable = unavailable.clone();
+            self.update_status(|s| s.network_unavailable = unavailable);
+        }
+
+        self.network_unavailable.is_none()
+    }
+
+    /// Check whether IPv6 servers can be reached, as the host may gain or lose its
+    /// IPv6 route at any time
+    fn detect_ipv6(&mut self) {
+        let available = network::has_ipv6_default_route();
+        if available == self.ipv6_available {
+            return;
+        }
+
+        self.ipv6_available = available;
+        if available {
+            info!(event = "ipv6_available"; "IPv6 default route found, probing the IPv6 DNS servers again");
+        } else {
+            warn!(event = "ipv6_unavailable"; "IPv6 default route lost, skipping the IPv6 DNS servers");
+        }
+    }
+
+    /// Servers the family policy allows and this host can reach
+    fn probed_servers(&self) -> Vec<(IpAddr, String)> {
+        self.servers
+            .iter()
+            .filter(|(address, _)| self.config.is_probed(*address, self.ipv6_available))
+            .cloned()
+            .collect()
+    }
+
+    /// Servers to probe this round, leaving out those in cooldown
+    fn probe_targets(&self) -> Vec<(IpAddr, String)> {
+        let now = Instant::now();
+        self.probed_servers()
+            .into_iter()
+            .filter(|(address, name)| {
+                let probe = self.health.should_probe(*address, now);
+                if !probe {
+                    debug!("Skipping DNS server {} ({}) during cooldown", name, address);
+                }
+                probe
+            })
+            .collect()
+    }
+
+    fn server_name(&self, address: IpAddr) -> &str {
+        self.servers
+            .iter()
+            .find(|(a, _)| *a == address)
+            .map(|(_, name)| name.as_str())
+            .unwrap_or(if self.config.fallback_servers.contains(&address) {
+                "fallback"
+            } else {
+                "unknown"
+            })
+    }
+
+    /// Write the selected servers to resolv.conf, returning whether it succeeded
+    fn apply_selection(&mut self, selected: Vec<IpAddr>) -> bool {
+        if self.paused {
+            info!("Paused: not writing {:?} to resolv.conf", selected);
+            return false;
+        }
+
+        match self.resolv_manager.update_dns_servers(&selected) {
+            Ok(()) => {
+                self.fallback = false;
+                let previous = self.written_servers(&self.selection);
+                self.selection = selected;
+                self.reapply_at = None;
+                let written = self.written_servers(&self.selection);
+                if previous != written {
+                    self.notify(HookPayload::new(
+                        HookEvent::Change,
+                        self.hook_servers(&previous),
+                        self.hook_servers(&written),
+                    ));
+                }
+                let selection: Vec<_> = written
+                    .iter()
+                    .map(|address| (*address, self.server_name(*address).to_string()))
+                    .collect();
+                self.update_status(|s| {
+                    s.selection = selection
+                        .iter()
+                        .map(|(address, name)| SelectedServer {
+                            name: name.clone(),
+                            address: *address,
+                        })
+                        .collect();
+                });
+                self.update_metrics(|m| {
+                    m.record_write(true);
+                    m.set_selection(selection);
+                });
+                true
+            }
+            Err(e) => {
+                self.report_error("write_failed", format!("Failed to update resolv.conf: {}", e));
+                self.update_metrics(|m| m.record_write(false));
+                self.notify(
+                    HookPayload::new(
+                        HookEvent::WriteError,
+                        self.hook_servers(&self.written_servers(&self.selection)),
+                        self.hook_servers(&self.written_servers(&selected)),
+                    )
+                    .with_error(format!("{:#}", e)),
+                );
+                false
+            }
+        }
+    }
+
+    /// Nameservers resolv.conf holds for a selection: the pinned servers first.
+    /// Nothing is written for an empty selection.
+    fn written_servers(&self, selection: &[IpAddr]) -> Vec<IpAddr> {
+        if selection.is_empty() {
+            return Vec::new();
+        }
+        self.resolv_manager.nameservers(selection)
+    }
+
+    /// Write the servers of the all-offline policy, unless resolv.conf already holds them,
+    /// then run the all-offline hook if the servers just ran out
+    fn apply_all_offline_policy(&mut self) {
+        let previous = self.written_servers(&self.selection);
+
+        if let Some(servers) = self.config.all_offline_servers() {
+            if !(self.fallback && self.selection == servers) {
+                warn!(event = "fallback_applied", selection:serde = servers;
+                    "All DNS servers are offline, writing {:?} to resolv.conf (on_all_offline: {})",
+                    servers, self.config.on_all_offline.name());
+                if self.apply_selection(servers) {
+                    self.fallback = true;
+                }
+            }
+        }
+
+        if self.all_offline {
+            return;
+        }
+        self.all_offline = true;
+
+        // Tell what resolv.conf lists now that the policy ran
+        let written = if self.fallback {
+            self.written_servers(&self.selection)
+        } else {
+            Vec::new()
+        };
+        self.notify(HookPayload::new(
+            HookEvent::AllOffline,
+            self.hook_servers(&previous),
+            self.hook_servers(&written),
+        ));
+    }
+
+    /// Run the recovery hook when servers are available again after all were offline
+    fn track_recovery(&mut self, available: &[IpAddr]) {
+        if available.is_empty() || !self.all_offline {
+            return;
+        }
+        self.all_offline = false;
+
+        info!(event = "recovery", selection:serde = available; "DNS servers are online again: {:?}", available);
+        self.notify(HookPayload::new(HookEvent::Recovery, Vec::new(), self.hook_servers(available)));
+    }
+
+    /// One line summary for `systemctl status`
+    fn describe_state(&self) -> String {
+        if self.paused {
+            format!("Paused, resolv.conf left alone (last selection: {})", self.describe_selection())
+        } else if let Some(reason) = &self.network_unavailable {
+            format!("Network unavailable ({}), keeping {}", reason, self.describe_selection())
+        } else if self.fallback {
+            format!("All DNS servers offline, using {}", self.describe_selection())
+        } else if self.all_offline {
+            format!("All DNS servers offline, keeping {}", self.describe_selection())
+        } else {
+            format!("Using {}", self.describe_selection())
+        }
+    }
+
+    fn publish_systemd_status(&mut self) {
+        let Some(notifier) = &self.notifier else {
+            return;
+        };
+        let status = self.describe_state();
+        if status != self.systemd_status {
+            notifier.status(&status);
+            self.systemd_status = status;
+        }
+    }
+
+    /// The result tables would only get in the way of a JSON log stream
+    fn print_tables(&self) -> bool {
+        self.config.log_format == LogFormat::Text
+    }
+
+    /// Tell hooks and the webhook about an event
+    fn notify(&self, payload: HookPayload) {
+        if let Some(webhook) = &self.webhook {
+            webhook.notify(&payload);
+        }
+        self.hooks.fire(payload);
+    }
+
+    fn hook_servers(&self, addresses: &[IpAddr]) -> Vec<HookServer> {
+        addresses
+            .iter()
+            .map(|address| HookServer {
+                name: self.server_name(*address).to_string(),
+                address: *address,
+                latency_ms: self.health.get(*address).and_then(|h| h.ewma_ms),
+            })
+            .collect()
+    }
+
+    fn reapply_selection(&mut self) {
+        let selection = self.selection.clone();
+        // Re-applied fallback servers are still only a fallback
+        let fallback = self.fallback;
+        if self.apply_selection(selection) {
+            self.fallback = fallback;
+            info!(event = "selection_reapplied", selection:serde = self.selection;
+                "Re-applied {} selected DNS servers", self.selection.len());
+        }
+    }
+
+    /// React to resolv.conf being rewritten by something other than us
+    fn handle_external_change(&mut self) {
+        // Nothing to defend until we have written a selection ourselves, and
+        // manual changes are expected while paused for maintenance
+        if self.selection.is_empty() || self.paused {
+            return;
+        }
+
+        let current = match self.resolv_manager.read_current_servers() {
+            Ok(current) => current,
+            Err(e) => {
+                warn!("Failed to read {} after change: {}", self.config.resolv_conf_path(), e);
+                Vec::new()
+            }
+        };
+
+        // Our own writes also trigger the watch
+        if current == self.resolv_manager.nameservers(&self.selection) {
+            return;
+        }
+
+        self.external_streak += 1;
+        self.update_metrics(|m| m.record_external_change());
+        let mut external_changes = 0;
+        self.update_status(|s| {
+            s.external_changes += 1;
+            external_changes = s.external_changes;
+        });
+
+        let writer = self.resolv_manager.detect_writer().unwrap_or("an unknown tool");
+        warn!(
+            event = "external_change", writer = writer, nameservers:serde = current;
+            "{} was modified externally, probably by {} (nameservers now {:?}, external change #{})",
+            self.config.resolv_conf_path(),
+            writer,
+            current,
+            external_changes
+        );
+
+        match self.config.watch.policy {
+            ExternalChangePolicy::Reapply => self.reapply_selection(),
+            ExternalChangePolicy::Backoff => {
+                // Double the delay for every change since the last scheduled round,
+                // but never wait longer than the round itself
+                let base = self.config.watch.backoff_seconds;
+                let delay = base
+                    .saturating_mul(1u64 << (self.external_streak - 1).min(16))
+                    .min(self.config.execution_interval_seconds.max(base));
+                info!("Backing off for {} seconds before re-applying selected DNS servers", delay);
+                self.reapply_at = Some(Instant::now() + Duration::from_secs(delay));
+            }
+        }
+    }
+}
+
+fn unknown_server(server: &str) -> Response {
+    Response::Error {
+        message: format!("No configured DNS server named {}", server),
+    }
+}
+
+/// Wait for the next change to resolv.conf, or forever if it is not watched
+async fn wait_for_change(watcher: &mut Option<ResolvConfWatcher>) -> Result<()> {
+    match watcher {
+        Some(watcher) => watcher.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for the next tick of an optional interval, or forever if there is none
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Sleep until the given deadline, or forever if there is none
+async fn wait_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
@@ -1,16 +1,21 @@
+use crate::config::DnsServer;
+use std::collections::BTreeMap;
 use std::net::IpAddr;
 use std::time::{Duration, Instant};
 use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
 use trust_dns_resolver::TokioAsyncResolver;
 use anyhow::Result;
 use log::{debug, info, warn};
+use serde::Serialize;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DnsCheckResult {
     pub address: IpAddr,
     pub name: String,
     pub is_online: bool,
     pub latency_ms: Option<f64>,
+    /// Why the server did not answer
+    pub error: Option<String>,
 }
 
 pub struct DnsChecker {
@@ -37,21 +42,25 @@ impl DnsChecker {
 
         match self.perform_dns_query(address).await {
             Ok(_) => {
-                info!("DNS server {} ({}) is ONLINE", name, address);
+                info!(event = "server_online", server = name, address:% = address;
+                    "DNS server {} ({}) is ONLINE", name, address);
                 DnsCheckResult {
                     address,
                     name: name.to_string(),
                     is_online: true,
                     latency_ms: None,
+                    error: None,
                 }
             }
             Err(e) => {
-                warn!("DNS server {} ({}) is OFFLINE: {}", name, address, e);
+                warn!(event = "server_offline", server = name, address:% = address, error:% = e;
+                    "DNS server {} ({}) is OFFLINE: {}", name, address, e);
                 DnsCheckResult {
                     address,
                     name: name.to_string(),
                     is_online: false,
                     latency_ms: None,
+                    error: Some(e.to_string()),
                 }
             }
         }
@@ -61,12 +70,10 @@ impl DnsChecker {
     pub async fn benchmark_dns(&self, address: IpAddr, name: &str) -> DnsCheckResult {
         debug!("Benchmarking DNS server: {} ({})", name, address);
 
-        let start = Instant::now();
-        match self.perform_dns_query(address).await {
-            Ok(_) => {
-                let latency = start.elapsed();
-                let latency_ms = latency.as_secs_f64() * 1000.0;
+        match self.measure(address).await {
+            Ok(latency_ms) => {
                 info!(
+                    event = "server_online", server = name, address:% = address, latency_ms = latency_ms;
                     "DNS server {} ({}) responded in {:.2}ms",
                     name, address, latency_ms
                 );
@@ -75,20 +82,30 @@ impl DnsChecker {
                     name: name.to_string(),
                     is_online: true,
                     latency_ms: Some(latency_ms),
+                    error: None,
                 }
             }
             Err(e) => {
-                warn!("DNS server {} ({}) failed benchmark: {}", name, address, e);
+                warn!(event = "server_offline", server = name, address:% = address, error:% = e;
+                    "DNS server {} ({}) failed benchmark: {}", name, address, e);
                 DnsCheckResult {
                     address,
                     name: name.to_string(),
                     is_online: false,
                     latency_ms: None,
+                    error: Some(e.to_string()),
                 }
             }
         }
     }
 
+    /// Latency of one query to the server, in milliseconds, without logging
+    pub async fn measure(&self, address: IpAddr) -> Result<f64> {
+        let start = Instant::now();
+        self.perform_dns_query(address).await?;
+        Ok(start.elapsed().as_secs_f64() * 1000.0)
+    }
+
     async fn perform_dns_query(&self, dns_server: IpAddr) -> Result<()> {
         // Create a custom resolver that only uses the specified DNS server
         let nameserver = NameServerConfig {
@@ -146,18 +163,46 @@ impl Default for DnsChecker {
     }
 }
 
-/// Select the best DNS servers based on latency
-pub fn select_best_dns(results: &[DnsCheckResult], count: usize) -> Vec<IpAddr> {
+/// Select first N online DNS servers from the list, lowest priority first and in
+/// list order otherwise
+pub fn select_first_online_dns(results: &[DnsCheckResult], servers: &[DnsServer], count: usize) -> Vec<IpAddr> {
+    let priority = |address: IpAddr| {
+        servers
+            .iter()
+            .find(|s| s.address == address)
+            .map_or(0, |s| s.priority)
+    };
+
+    let mut online: Vec<_> = results.iter().filter(|r| r.is_online).collect();
+    // Stable, so the list order is kept within a priority
+    online.sort_by_key(|r| priority(r.address));
+
+    online
+        .iter()
+        .take(count)
+        .map(|r| r.address)
+        .collect()
+}
+
+/// Select the best DNS servers based on latency, adjusted by each server's
+/// latency_bias_ms and weight
+pub fn select_best_dns(results: &[DnsCheckResult], servers: &[DnsServer], count: usize) -> Vec<IpAddr> {
+    let score = |result: &DnsCheckResult| {
+        let latency = result.latency_ms.unwrap_or(f64::MAX);
+        match servers.iter().find(|s| s.address == result.address) {
+            Some(server) => server.score(latency),
+            None => latency,
+        }
+    };
+
     let mut online_servers: Vec<_> = results
         .iter()
         .filter(|r| r.is_online && r.latency_ms.is_some())
         .collect();
 
-    // Sort by latency (lowest first)
+    // Sort by effective latency (lowest first)
     online_servers.sort_by(|a, b| {
-        let latency_a = a.latency_ms.unwrap_or(f64::MAX);
-        let latency_b = b.latency_ms.unwrap_or(f64::MAX);
-        latency_a.partial_cmp(&latency_b).unwrap_or(std::cmp::Ordering::Equal)
+        score(a).partial_cmp(&score(b)).unwrap_or(std::cmp::Ordering::Equal)
     });
 
     // Take the best ones
@@ -167,3 +212,209 @@ pub fn select_best_dns(results: &[DnsCheckResult], count: usize) -> Vec<IpAddr>
         .map(|r| r.address)
         .collect()
 }
+
+/// Rank the DNS servers by tier (priority) and effective latency within each
+/// tier, putting first the most preferred tier with at least `min` online servers
+pub fn select_tiered_dns(results: &[DnsCheckResult], servers: &[DnsServer], min: usize) -> Vec<IpAddr> {
+    let mut tiers: BTreeMap<u32, Vec<IpAddr>> = BTreeMap::new();
+    for address in select_best_dns(results, servers, results.len()) {
+        let tier = servers
+            .iter()
+            .find(|s| s.address == address)
+            .map_or(0, |s| s.priority);
+        tiers.entry(tier).or_default().push(address);
+    }
+
+    // The other tiers follow in order, for failover or when no tier is healthy enough
+    let healthy = tiers.iter().find(|(_, tier)| tier.len() >= min).map(|(priority, _)| *priority);
+    let mut ranked = healthy.and_then(|priority| tiers.remove(&priority)).unwrap_or_default();
+    ranked.extend(tiers.into_values().flatten());
+    ranked
+}
+
+/// Builders shared by the unit tests of the selection code
+#[cfg(test)]
+pub mod test_support {
+    use super::*;
+
+    pub fn server(address: &str, priority: u32, latency_bias_ms: f64, weight: f64) -> DnsServer {
+        DnsServer {
+            name: address.to_string(),
+            address: address.parse().unwrap(),
+            provider: None,
+            priority,
+            latency_bias_ms,
+            weight,
+            pinned: false,
+        }
+    }
+
+    pub fn online(address: &str, latency_ms: f64) -> DnsCheckResult {
+        DnsCheckResult {
+            address: address.parse().unwrap(),
+            name: address.to_string(),
+            is_online: true,
+            latency_ms: Some(latency_ms),
+            error: None,
+        }
+    }
+
+    pub fn offline(address: &str) -> DnsCheckResult {
+        DnsCheckResult {
+            address: address.parse().unwrap(),
+            name: address.to_string(),
+            is_online: false,
+            latency_ms: None,
+            error: Some("timed out".to_string()),
+        }
+    }
+
+    pub fn addresses(addresses: &[&str]) -> Vec<IpAddr> {
+        addresses.iter().map(|address| address.parse().unwrap()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_support::{addresses, offline, online, server};
+    use super::*;
+
+    #[test]
+    fn test_first_online_dns_orders_by_priority_then_list_order() {
+        let servers = [
+            server("192.0.2.1", 1, 0.0, 1.0),
+            server("192.0.2.2", 0, 0.0, 1.0),
+            server("192.0.2.3", 1, 0.0, 1.0),
+            server("192.0.2.4", 0, 0.0, 1.0),
+        ];
+        let results = [
+            online("192.0.2.1", 10.0),
+            online("192.0.2.2", 10.0),
+            online("192.0.2.3", 10.0),
+            offline("192.0.2.4"),
+        ];
+
+        assert_eq!(
+            select_first_online_dns(&results, &servers, 4),
+            addresses(&["192.0.2.2", "192.0.2.1", "192.0.2.3"])
+        );
+        assert_eq!(select_first_online_dns(&results, &servers, 2), addresses(&["192.0.2.2", "192.0.2.1"]));
+    }
+
+    #[test]
+    fn test_best_dns_ranks_by_latency_and_skips_offline_servers() {
+        let servers = [
+            server("192.0.2.1", 0, 0.0, 1.0),
+            server("192.0.2.2", 0, 0.0, 1.0),
+            server("192.0.2.3", 0, 0.0, 1.0),
+        ];
+        let results = [online("192.0.2.1", 30.0), offline("192.0.2.2"), online("192.0.2.3", 10.0)];
+
+        assert_eq!(select_best_dns(&results, &servers, 3), addresses(&["192.0.2.3", "192.0.2.1"]));
+        assert_eq!(select_best_dns(&results, &servers, 1), addresses(&["192.0.2.3"]));
+    }
+
+    #[test]
+    fn test_best_dns_applies_latency_bias() {
+        // 30ms - 25ms beats 10ms, 5ms + 10ms does not
+        let servers = [
+            server("192.0.2.1", 0, -25.0, 1.0),
+            server("192.0.2.2", 0, 0.0, 1.0),
+            server("192.0.2.3", 0, 10.0, 1.0),
+        ];
+        let results = [online("192.0.2.1", 30.0), online("192.0.2.2", 10.0), online("192.0.2.3", 5.0)];
+
+        assert_eq!(
+            select_best_dns(&results, &servers, 3),
+            addresses(&["192.0.2.1", "192.0.2.2", "192.0.2.3"])
+        );
+    }
+
+    #[test]
+    fn test_best_dns_applies_weight() {
+        // 30ms / 4 beats 10ms, 5ms / 0.25 does not
+        let servers = [
+            server("192.0.2.1", 0, 0.0, 4.0),
+            server("192.0.2.2", 0, 0.0, 1.0),
+            server("192.0.2.3", 0, 0.0, 0.25),
+        ];
+        let results = [online("192.0.2.1", 30.0), online("192.0.2.2", 10.0), online("192.0.2.3", 5.0)];
+
+        assert_eq!(
+            select_best_dns(&results, &servers, 3),
+            addresses(&["192.0.2.1", "192.0.2.2", "192.0.2.3"])
+        );
+    }
+
+    #[test]
+    fn test_tiered_dns_prefers_healthy_tier_by_latency() {
+        let servers = [
+            server("192.0.2.1", 0, 0.0, 1.0),
+            server("192.0.2.2", 0, 0.0, 1.0),
+            server("192.0.2.3", 1, 0.0, 1.0),
+        ];
+        let results = [online("192.0.2.1", 30.0), online("192.0.2.2", 20.0), online("192.0.2.3", 1.0)];
+
+        // The faster server of the second tier only comes after the first tier
+        assert_eq!(
+            select_tiered_dns(&results, &servers, 2),
+            addresses(&["192.0.2.2", "192.0.2.1", "192.0.2.3"])
+        );
+    }
+
+    #[test]
+    fn test_tiered_dns_skips_tier_with_too_few_online_servers() {
+        let servers = [
+            server("192.0.2.1", 0, 0.0, 1.0),
+            server("192.0.2.2", 0, 0.0, 1.0),
+            server("192.0.2.3", 1, 0.0, 1.0),
+            server("192.0.2.4", 1, 0.0, 1.0),
+            server("192.0.2.5", 2, 0.0, 1.0),
+        ];
+        let results = [
+            online("192.0.2.1", 5.0),
+            offline("192.0.2.2"),
+            online("192.0.2.3", 40.0),
+            online("192.0.2.4", 30.0),
+            online("192.0.2.5", 1.0),
+        ];
+
+        // Tier 1 is healthy, the rest follows in tier order
+        assert_eq!(
+            select_tiered_dns(&results, &servers, 2),
+            addresses(&["192.0.2.4", "192.0.2.3", "192.0.2.1", "192.0.2.5"])
+        );
+    }
+
+    #[test]
+    fn test_tiered_dns_keeps_tier_order_without_healthy_tier() {
+        let servers = [
+            server("192.0.2.1", 0, 0.0, 1.0),
+            server("192.0.2.2", 0, 0.0, 1.0),
+            server("192.0.2.3", 1, 0.0, 1.0),
+        ];
+        let results = [offline("192.0.2.1"), online("192.0.2.2", 20.0), online("192.0.2.3", 1.0)];
+
+        assert_eq!(select_tiered_dns(&results, &servers, 2), addresses(&["192.0.2.2", "192.0.2.3"]));
+    }
+
+    #[test]
+    fn test_tiered_dns_scores_within_tier() {
+        // 30ms - 25ms beats 10ms within the tier
+        let servers = [
+            server("192.0.2.1", 0, -25.0, 1.0),
+            server("192.0.2.2", 0, 0.0, 1.0),
+        ];
+        let results = [online("192.0.2.1", 30.0), online("192.0.2.2", 10.0)];
+
+        assert_eq!(select_tiered_dns(&results, &servers, 2), addresses(&["192.0.2.1", "192.0.2.2"]));
+    }
+
+    #[test]
+    fn test_best_dns_ignores_priority() {
+        let servers = [server("192.0.2.1", 0, 0.0, 1.0), server("192.0.2.2", 1, 0.0, 1.0)];
+        let results = [online("192.0.2.1", 20.0), online("192.0.2.2", 10.0)];
+
+        assert_eq!(select_best_dns(&results, &servers, 2), addresses(&["192.0.2.2", "192.0.2.1"]));
+    }
+}
@@ -0,0 +1,452 @@
+use crate::config::HealthConfig;
+use crate::dns_checker::DnsCheckResult;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerState {
+    Unknown, // Not probed yet
+    Up,
+    Down,
+}
+
+/// Outcome of a single probe
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Sample {
+    pub online: bool,
+    pub latency_ms: Option<f64>,
+}
+
+/// Health of a single DNS server across rounds
+#[derive(Debug, Clone)]
+pub struct ServerHealth {
+    pub state: ServerState,
+    pub consecutive_failures: u32,
+    pub consecutive_successes: u32,
+    /// Current cooldown, doubled every time a probe after cooldown fails
+    pub cooldown: Duration,
+    /// The server is not probed again before this time
+    pub cooldown_until: Option<Instant>,
+    /// Exponentially weighted moving average of the measured latency
+    pub ewma_ms: Option<f64>,
+    /// Most recent probe outcomes, oldest first
+    pub samples: VecDeque<Sample>,
+}
+
+impl ServerHealth {
+    fn new() -> Self {
+        Self {
+            state: ServerState::Unknown,
+            consecutive_failures: 0,
+            consecutive_successes: 0,
+            cooldown: Duration::ZERO,
+            cooldown_until: None,
+            ewma_ms: None,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Mean latency over the successful probes in the window
+    pub fn window_mean_ms(&self) -> Option<f64> {
+        let latencies: Vec<f64> = self.samples.iter().filter_map(|s| s.latency_ms).collect();
+        if latencies.is_empty() {
+            None
+        } else {
+            Some(latencies.iter().sum::<f64>() / latencies.len() as f64)
+        }
+    }
+
+    /// Share of failed probes in the window
+    pub fn loss_ratio(&self) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let failed = self.samples.iter().filter(|s| !s.online).count();
+        failed as f64 / self.samples.len() as f64
+    }
+
+    fn push_sample(&mut self, sample: Sample, settings: &HealthConfig) {
+        if let Some(latency) = sample.latency_ms {
+            let alpha = settings.smoothing_factor;
+            self.ewma_ms = Some(match self.ewma_ms {
+                Some(ewma) => alpha * latency + (1.0 - alpha) * ewma,
+                None => latency,
+            });
+        }
+
+        self.samples.push_back(sample);
+        while self.samples.len() > settings.history_size {
+            self.samples.pop_front();
+        }
+    }
+}
+
+/// Circuit breaker and latency history over the probe results of every configured server.
+///
+/// A server is marked down after `failure_threshold` consecutive failures and up
+/// again after `recovery_threshold` consecutive successes. While down it sits out
+/// a cooldown that doubles after every failed probe, up to `max_cooldown_seconds`.
+///
+/// Latencies are smoothed with an EWMA (`smoothing_factor`), so a single lucky or
+/// unlucky round does not decide the ranking.
+pub struct HealthTracker {
+    settings: HealthConfig,
+    servers: HashMap<IpAddr, ServerHealth>,
+}
+
+impl HealthTracker {
+    pub fn new(settings: HealthConfig) -> Self {
+        Self {
+            settings,
+            servers: HashMap::new(),
+        }
+    }
+
+    /// Seed the tracker with health restored from a previous run, keeping only
+    /// servers that are still configured
+    pub fn restore(&mut self, restored: Vec<(IpAddr, ServerHealth)>, servers: &[(IpAddr, String)]) {
+        for (address, mut health) in restored {
+            if !servers.iter().any(|(a, _)| *a == address) {
+                continue;
+            }
+            while health.samples.len() > self.settings.history_size {
+                health.samples.pop_front();
+            }
+            self.servers.insert(address, health);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&IpAddr, &ServerHealth)> {
+        self.servers.iter()
+    }
+
+    pub fn get(&self, address: IpAddr) -> Option<&ServerHealth> {
+        self.servers.get(&address)
+    }
+
+    pub fn is_up(&self, address: IpAddr) -> bool {
+        self.get(address).map(|h| h.state == ServerState::Up).unwrap_or(false)
+    }
+
+    /// Whether the server may be probed, i.e. it is not cooling down
+    pub fn should_probe(&self, address: IpAddr, now: Instant) -> bool {
+        match self.get(address).and_then(|h| h.cooldown_until) {
+            Some(until) => now >= until,
+            None => true,
+        }
+    }
+
+    /// Record the outcome of one probe and update the server state
+    pub fn record(&mut self, result: &DnsCheckResult, now: Instant) {
+        let settings = &self.settings;
+        let (address, name, online) = (result.address, result.name.as_str(), result.is_online);
+        let health = self.servers.entry(address).or_insert_with(ServerHealth::new);
+
+        health.push_sample(
+            Sample {
+                online,
+                latency_ms: if online { result.latency_ms } else { None },
+            },
+            settings,
+        );
+
+        if online {
+            health.consecutive_successes += 1;
+            health.consecutive_failures = 0;
+            health.cooldown_until = None;
+
+            let recovered = match health.state {
+                ServerState::Unknown => true,
+                ServerState::Down => health.consecutive_successes >= settings.recovery_threshold,
+                ServerState::Up => false,
+            };
+            if recovered {
+                if health.state == ServerState::Down {
+                    info!(
+                        event = "server_up", server = name, address:% = address;
+                        "DNS server {} ({}) marked UP after {} consecutive successes",
+                        name, address, health.consecutive_successes
+                    );
+                }
+                health.state = ServerState::Up;
+                health.cooldown = Duration::ZERO;
+            }
+        } else {
+            health.consecutive_failures += 1;
+            health.consecutive_successes = 0;
+
+            match health.state {
+                ServerState::Up | ServerState::Unknown
+                    if health.consecutive_failures < settings.failure_threshold =>
+                {
+                    warn!(
+                        event = "server_probe_failed", server = name, address:% = address;
+                        "DNS server {} ({}) failed {}/{} consecutive probes",
+                        name, address, health.consecutive_failures, settings.failure_threshold
+                    );
+                    return;
+                }
+                ServerState::Down => {
+                    // Still failing after the cooldown: back off further
+                    health.cooldown = health
+                        .cooldown
+                        .saturating_mul(2)
+                        .min(Duration::from_secs(settings.max_cooldown_seconds));
+                }
+                ServerState::Up | ServerState::Unknown => {
+                    warn!(
+                        event = "server_down", server = name, address:% = address;
+                        "DNS server {} ({}) marked DOWN after {} consecutive failures",
+                        name, address, health.consecutive_failures
+                    );
+                    health.state = ServerState::Down;
+                    health.cooldown = Duration::from_secs(settings.cooldown_seconds);
+                }
+            }
+
+            // The cooldown is bounded by the configuration, but a deadline off the
+            // clock would panic; the server is then simply probed again
+            health.cooldown_until = now.checked_add(health.cooldown);
+            info!(
+                event = "server_cooldown", server = name, address:% = address,
+                cooldown_seconds = health.cooldown.as_secs();
+                "DNS server {} ({}) will not be probed for {} seconds",
+                name,
+                address,
+                health.cooldown.as_secs()
+            );
+        }
+    }
+
+    /// Record a round of probe results and return what the selection should see:
+    /// one result per configured server, online only while the server is up, with
+    /// the smoothed latency. Servers that were not probed (cooling down) are reported offline.
+    pub fn record_round(
+        &mut self,
+        servers: &[(IpAddr, String)],
+        results: &[DnsCheckResult],
+        now: Instant,
+    ) -> Vec<DnsCheckResult> {
+        for result in results {
+            self.record(result, now);
+        }
+
+        servers
+            .iter()
+            .map(|(address, name)| {
+                let health = self.get(*address);
+                if let Some(health) = health {
+                    debug!(
+                        "DNS server {} ({}): smoothed {}, window mean {}, loss {:.0}% over {} samples",
+                        name,
+                        address,
+                        format_latency(health.ewma_ms),
+                        format_latency(health.window_mean_ms()),
+                        health.loss_ratio() * 100.0,
+                        health.samples.len()
+                    );
+                }
+
+                DnsCheckResult {
+                    address: *address,
+                    name: name.clone(),
+                    is_online: self.is_up(*address),
+                    latency_ms: health.and_then(|h| h.ewma_ms),
+                    error: results
+                        .iter()
+                        .find(|r| r.address == *address)
+                        .and_then(|r| r.error.clone()),
+                }
+            })
+            .collect()
+    }
+}
+
+fn format_latency(latency_ms: Option<f64>) -> String {
+    match latency_ms {
+        Some(latency) => format!("{:.2}ms", latency),
+        None => "-".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_checker::test_support::{offline, online};
+
+    fn state(tracker: &HealthTracker, address: &str) -> ServerState {
+        tracker.get(address.parse().unwrap()).unwrap().state
+    }
+
+    #[test]
+    fn test_server_goes_down_after_failure_threshold_and_recovers() {
+        let mut tracker = HealthTracker::new(HealthConfig::default());
+        let address: IpAddr = "192.0.2.1".parse().unwrap();
+        let now = Instant::now();
+
+        // Unknown servers need as many failures as servers that were up
+        tracker.record(&offline("192.0.2.1"), now);
+        assert_eq!(state(&tracker, "192.0.2.1"), ServerState::Unknown);
+        assert!(tracker.should_probe(address, now));
+
+        tracker.record(&offline("192.0.2.1"), now);
+        assert_eq!(state(&tracker, "192.0.2.1"), ServerState::Down);
+        assert!(!tracker.should_probe(address, now + Duration::from_secs(59)));
+        assert!(tracker.should_probe(address, now + Duration::from_secs(60)));
+
+        let later = now + Duration::from_secs(60);
+        tracker.record(&online("192.0.2.1", 10.0), later);
+        assert!(!tracker.is_up(address), "One success should not be enough to recover");
+        assert!(tracker.should_probe(address, later));
+
+        tracker.record(&online("192.0.2.1", 10.0), later);
+        assert!(tracker.is_up(address));
+        assert_eq!(tracker.get(address).unwrap().cooldown, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_first_success_marks_unknown_server_up() {
+        let mut tracker = HealthTracker::new(HealthConfig::default());
+
+        tracker.record(&online("192.0.2.1", 10.0), Instant::now());
+
+        assert_eq!(state(&tracker, "192.0.2.1"), ServerState::Up);
+    }
+
+    #[test]
+    fn test_cooldown_doubles_up_to_the_maximum() {
+        let mut tracker = HealthTracker::new(HealthConfig {
+            failure_threshold: 1,
+            cooldown_seconds: 60,
+            max_cooldown_seconds: 200,
+            ..HealthConfig::default()
+        });
+        let address: IpAddr = "192.0.2.1".parse().unwrap();
+        let now = Instant::now();
+
+        let mut cooldowns = Vec::new();
+        for _ in 0..4 {
+            tracker.record(&offline("192.0.2.1"), now);
+            cooldowns.push(tracker.get(address).unwrap().cooldown.as_secs());
+        }
+
+        assert_eq!(cooldowns, vec![60, 120, 200, 200]);
+        assert_eq!(tracker.get(address).unwrap().cooldown_until, Some(now + Duration::from_secs(200)));
+    }
+
+    #[test]
+    fn test_ewma_smooths_latency_and_ignores_failures() {
+        let mut tracker = HealthTracker::new(HealthConfig {
+            smoothing_factor: 0.5,
+            ..HealthConfig::default()
+        });
+        let address: IpAddr = "192.0.2.1".parse().unwrap();
+        let now = Instant::now();
+
+        let mut ewma = Vec::new();
+        for result in [
+            online("192.0.2.1", 10.0),
+            online("192.0.2.1", 20.0),
+            offline("192.0.2.1"),
+            online("192.0.2.1", 30.0),
+        ] {
+            tracker.record(&result, now);
+            ewma.push(tracker.get(address).unwrap().ewma_ms.unwrap());
+        }
+
+        assert_eq!(ewma, vec![10.0, 15.0, 15.0, 22.5]);
+    }
+
+    #[test]
+    fn test_loss_ratio_and_mean_cover_only_the_window() {
+        let mut tracker = HealthTracker::new(HealthConfig {
+            failure_threshold: 10,
+            history_size: 4,
+            ..HealthConfig::default()
+        });
+        let address: IpAddr = "192.0.2.1".parse().unwrap();
+        let now = Instant::now();
+
+        for result in [
+            online("192.0.2.1", 100.0),
+            offline("192.0.2.1"),
+            online("192.0.2.1", 10.0),
+            offline("192.0.2.1"),
+            offline("192.0.2.1"),
+        ] {
+            tracker.record(&result, now);
+        }
+
+        let health = tracker.get(address).unwrap();
+        assert_eq!(health.samples.len(), 4);
+        assert_eq!(health.loss_ratio(), 0.75);
+        // The 100ms sample fell out of the window
+        assert_eq!(health.window_mean_ms(), Some(10.0));
+        assert_eq!(ServerHealth::new().loss_ratio(), 0.0);
+        assert_eq!(ServerHealth::new().window_mean_ms(), None);
+    }
+
+    #[test]
+    fn test_restore_keeps_configured_servers_and_trims_history() {
+        let mut tracker = HealthTracker::new(HealthConfig {
+            history_size: 2,
+            ..HealthConfig::default()
+        });
+        let configured: IpAddr = "192.0.2.1".parse().unwrap();
+        let removed: IpAddr = "192.0.2.9".parse().unwrap();
+
+        let mut health = ServerHealth::new();
+        health.state = ServerState::Up;
+        health.samples = (0..5)
+            .map(|i| Sample {
+                online: true,
+                latency_ms: Some(i as f64),
+            })
+            .collect();
+
+        tracker.restore(
+            vec![(configured, health.clone()), (removed, health)],
+            &[(configured, "Lab".to_string())],
+        );
+
+        assert!(tracker.is_up(configured));
+        assert!(tracker.get(removed).is_none());
+        let latencies: Vec<_> = tracker.get(configured).unwrap().samples.iter().map(|s| s.latency_ms).collect();
+        assert_eq!(latencies, vec![Some(3.0), Some(4.0)], "The newest samples should be kept");
+    }
+
+    #[test]
+    fn test_round_reports_every_server_with_smoothed_latency() {
+        let mut tracker = HealthTracker::new(HealthConfig {
+            failure_threshold: 1,
+            smoothing_factor: 0.5,
+            ..HealthConfig::default()
+        });
+        let servers: Vec<(IpAddr, String)> = ["192.0.2.1", "192.0.2.2", "192.0.2.3"]
+            .iter()
+            .map(|address| (address.parse().unwrap(), address.to_string()))
+            .collect();
+        let now = Instant::now();
+
+        tracker.record_round(&servers, &[online("192.0.2.1", 10.0), offline("192.0.2.2")], now);
+        let round = tracker.record_round(&servers, &[online("192.0.2.1", 30.0)], now);
+
+        assert_eq!(round.len(), 3);
+        assert!(round[0].is_online);
+        assert_eq!(round[0].latency_ms, Some(20.0));
+        // Cooling down, so not probed this round, but still down
+        assert!(!round[1].is_online);
+        assert_eq!(round[1].error, None);
+        // Never probed
+        assert!(!round[2].is_online);
+        assert_eq!(round[2].latency_ms, None);
+
+        let round = tracker.record_round(&servers, &[offline("192.0.2.3")], now);
+        assert_eq!(round[2].error.as_deref(), Some("timed out"));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::config::HooksConfig;
+use crate::status::{unix_now, SharedStatus};
+use crate::systemd;
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Events that can run a hook
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    Change,
+    AllOffline,
+    Recovery,
+    WriteError,
+}
+
+impl HookEvent {
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::Change => "change",
+            HookEvent::AllOffline => "all_offline",
+            HookEvent::Recovery => "recovery",
+            HookEvent::WriteError => "write_error",
+        }
+    }
+}
+
+/// A server as described to hooks
+#[derive(Debug, Clone, Serialize)]
+pub struct HookServer {
+    pub name: String,
+    pub address: IpAddr,
+    /// Smoothed latency, if the server answered recently
+    pub latency_ms: Option<f64>,
+}
+
+/// What a hook is told about the event, as JSON on stdin and as environment variables
+#[derive(Debug, Clone, Serialize)]
+pub struct HookPayload {
+    pub event: HookEvent,
+    /// Unix timestamp, in seconds
+    pub timestamp: u64,
+    pub old_servers: Vec<HookServer>,
+    pub new_servers: Vec<HookServer>,
+    pub error: Option<String>,
+}
+
+impl HookPayload {
+    pub fn new(event: HookEvent, old_servers: Vec<HookServer>, new_servers: Vec<HookServer>) -> Self {
+        Self {
+            event,
+            timestamp: unix_now(),
+            old_servers,
+            new_servers,
+            error: None,
+        }
+    }
+
+    pub fn with_error(mut self, error: String) -> Self {
+        self.error = Some(error);
+        self
+    }
+
+    fn environment(&self) -> Vec<(String, String)> {
+        let mut env = vec![
+            ("AUTODNS_EVENT".to_string(), self.event.name().to_string()),
+            ("AUTODNS_TIMESTAMP".to_string(), self.timestamp.to_string()),
+        ];
+        for (prefix, servers) in [("AUTODNS_OLD", &self.old_servers), ("AUTODNS_NEW", &self.new_servers)] {
+            env.push((format!("{}_SERVERS", prefix), join(servers, |s| s.address.to_string())));
+            env.push((format!("{}_NAMES", prefix), join(servers, |s| s.name.clone())));
+            env.push((
+                format!("{}_LATENCIES_MS", prefix),
+                join(servers, |s| match s.latency_ms {
+                    Some(latency) => format!("{:.2}", latency),
+                    None => "-".to_string(),
+                }),
+            ));
+        }
+        if let Some(error) = &self.error {
+            env.push(("AUTODNS_ERROR".to_string(), error.clone()));
+        }
+        env
+    }
+}
+
+/// Space separated list, as usable in a shell `for` loop
+fn join(servers: &[HookServer], field: impl Fn(&HookServer) -> String) -> String {
+    servers.iter().map(field).collect::<Vec<_>>().join(" ")
+}
+
+/// Runs the configured hook commands in the background
+pub struct Hooks {
+    config: HooksConfig,
+    status: SharedStatus,
+}
+
+impl Hooks {
+    pub fn new(config: HooksConfig, status: SharedStatus) -> Self {
+        Self { config, status }
+    }
+
+    fn command(&self, event: HookEvent) -> Option<&String> {
+        match event {
+            HookEvent::Change => self.config.on_change.as_ref(),
+            HookEvent::AllOffline => self.config.on_all_offline.as_ref(),
+            HookEvent::Recovery => self.config.on_recovery.as_ref(),
+            HookEvent::WriteError => self.config.on_write_error.as_ref(),
+        }
+    }
+
+    /// Start the hook for this event, if one is configured, without waiting for it
+    pub fn fire(&self, payload: HookPayload) {
+        let Some(command) = self.command(payload.event).cloned() else {
+            return;
+        };
+
+        let timeout = Duration::from_secs(self.config.timeout_seconds);
+        let status = self.status.clone();
+        info!(event = "hook_started", hook = payload.event.name(); "Running {} hook: {}", payload.event.name(), command);
+
+        tokio::spawn(async move {
+            if let Err(e) = run_hook(&command, &payload, timeout).await {
+                let message = format!("{} hook failed: {:#}", payload.event.name(), e);
+                warn!(event = "hook_failed", hook = payload.event.name(), error = message.as_str(); "{}", message);
+                if let Ok(mut status) = status.lock() {
+                    status.record_error(message);
+                }
+            }
+        });
+    }
+}
+
+async fn run_hook(command: &str, payload: &HookPayload, timeout: Duration) -> Result<()> {
+    let input = serde_json::to_vec(payload).context("Failed to serialize hook payload")?;
+
+    let mut shell = Command::new("/bin/sh");
+    shell.arg("-c").arg(command).envs(payload.environment());
+    for name in systemd::NOTIFY_ENV {
+        shell.env_remove(name);
+    }
+
+    let mut child = shell
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to start `{}`", command))?;
+
+    let stdin = child.stdin.take();
+    let run = async move {
+        // A hook that does not read its stdin must not make us fail
+        if let Some(mut stdin) = stdin {
+            if let Err(e) = stdin.write_all(&input).await {
+                debug!("Hook did not read its input: {}", e);
+            }
+        }
+        child.wait_with_output().await
+    };
+
+    // Dropping the child on timeout kills it
+    let output = match tokio::time::timeout(timeout, run).await {
+        Ok(output) => output.context("Failed to wait for hook")?,
+        Err(_) => bail!("killed after {} seconds", timeout.as_secs()),
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for line in stdout.lines().chain(stderr.lines()) {
+        debug!("{} hook: {}", payload.event.name(), line);
+    }
+
+    if !output.status.success() {
+        bail!("exited with {}: {}", output.status, stderr.trim());
+    }
+
+    debug!("{} hook finished", payload.event.name());
+    Ok(())
+}
@@ -0,0 +1,355 @@
+use clap::ValueEnum;
+use env_logger::fmt::Formatter;
+use log::kv::{self, Key, Value, VisitSource};
+use log::{Level, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+use std::io::Write;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+
+/// Native protocol socket of systemd-journald
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Local syslog socket
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Syslog facility "daemon"
+const SYSLOG_FACILITY: u8 = 3;
+
+const IDENTIFIER: &str = "autodns";
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,  // Human readable lines
+    Json,  // One JSON object per line, with the structured fields of the event
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTarget {
+    #[default]
+    Stderr,    // Written to stderr, captured by systemd or the terminal
+    Journald,  // Sent to systemd-journald with structured AUTODNS_* fields
+    Syslog,    // Sent to the local syslog daemon over /dev/log
+}
+
+/// Set up the global logger; RUST_LOG still selects the level
+pub fn init(format: LogFormat, target: LogTarget) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+
+    if format == LogFormat::Json {
+        builder.format(format_json);
+    }
+
+    let sink = match target {
+        LogTarget::Stderr => None,
+        LogTarget::Journald => Some(Sink::journald()),
+        LogTarget::Syslog => Some(Sink::syslog()),
+    };
+
+    match sink {
+        None => builder.init(),
+        Some(Ok(sink)) => {
+            let filter = builder.build();
+            log::set_max_level(filter.filter());
+            let logger = SocketLogger { filter, sink, format };
+            if log::set_boxed_logger(Box::new(logger)).is_err() {
+                eprintln!("⚠ WARNING: Logger was already initialized");
+            }
+        }
+        Some(Err(e)) => {
+            builder.init();
+            log::warn!("Cannot log to {:?}, logging to stderr instead: {}", target, e);
+        }
+    }
+}
+
+/// Format a record as a JSON object: timestamp, level, target and message, plus
+/// the key-values attached to the log call (event, server, address, latency_ms,
+/// selection, error...)
+fn format_json(buf: &mut Formatter, record: &Record) -> std::io::Result<()> {
+    writeln!(buf, "{}", json_entry(record))
+}
+
+fn json_entry(record: &Record) -> serde_json::Value {
+    let mut entry = Map::new();
+    entry.insert(
+        "timestamp".to_string(),
+        chrono::Utc::now()
+            .to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+            .into(),
+    );
+    entry.insert("level".to_string(), record.level().as_str().to_lowercase().into());
+    entry.insert("target".to_string(), record.target().into());
+    entry.insert("message".to_string(), record.args().to_string().into());
+
+    let _ = record.key_values().visit(&mut JsonFields(&mut entry));
+
+    serde_json::Value::Object(entry)
+}
+
+struct JsonFields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = serde_json::to_value(&value).unwrap_or_else(|_| value.to_string().into());
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Key-values of a record as plain text, lists separated by spaces
+struct TextFields(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for TextFields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let text = match serde_json::to_value(&value) {
+            Ok(serde_json::Value::String(text)) => text,
+            Ok(serde_json::Value::Array(items)) => items
+                .iter()
+                .map(|item| match item {
+                    serde_json::Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+            Ok(other) => other.to_string(),
+            Err(_) => value.to_string(),
+        };
+        self.0.push((key.to_string(), text));
+        Ok(())
+    }
+}
+
+fn text_fields(record: &Record) -> Vec<(String, String)> {
+    let mut fields = TextFields(Vec::new());
+    let _ = record.key_values().visit(&mut fields);
+    fields.0
+}
+
+/// Syslog severity, also used as the journald PRIORITY
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+enum Sink {
+    Journald(UnixDatagram),
+    /// Reconnected if the syslog daemon restarts
+    Syslog(Mutex<UnixDatagram>),
+}
+
+impl Sink {
+    fn journald() -> std::io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNALD_SOCKET)?;
+        Ok(Sink::Journald(socket))
+    }
+
+    fn syslog() -> std::io::Result<Self> {
+        Ok(Sink::Syslog(Mutex::new(connect_syslog()?)))
+    }
+}
+
+fn connect_syslog() -> std::io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(SYSLOG_SOCKET)?;
+    Ok(socket)
+}
+
+/// Logger sending records to journald or syslog, filtered like env_logger
+struct SocketLogger {
+    filter: env_logger::Logger,
+    sink: Sink,
+    format: LogFormat,
+}
+
+impl SocketLogger {
+    fn send(&self, record: &Record) -> std::io::Result<()> {
+        match &self.sink {
+            Sink::Journald(socket) => {
+                socket.send(&journald_entry(record))?;
+            }
+            Sink::Syslog(socket) => {
+                let message = match self.format {
+                    LogFormat::Json => json_entry(record).to_string(),
+                    LogFormat::Text => syslog_text(record),
+                };
+                let line = syslog_line(record.level(), chrono::Local::now(), &message);
+
+                let mut socket = socket.lock().unwrap_or_else(|e| e.into_inner());
+                if socket.send(line.as_bytes()).is_err() {
+                    *socket = connect_syslog()?;
+                    socket.send(line.as_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Log for SocketLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        if let Err(e) = self.send(record) {
+            // Never lose a record because the log daemon is unavailable
+            eprintln!("[{} {}] {} (logging failed: {})", record.level(), record.target(), record.args(), e);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// RFC 3164 line: priority, local timestamp and tag, then the message
+fn syslog_line(level: Level, time: chrono::DateTime<chrono::Local>, message: &str) -> String {
+    format!(
+        "<{}>{} {}[{}]: {}",
+        SYSLOG_FACILITY * 8 + severity(level),
+        time.format("%b %e %H:%M:%S"),
+        IDENTIFIER,
+        std::process::id(),
+        message
+    )
+}
+
+/// Message followed by the structured fields as key=value pairs
+fn syslog_text(record: &Record) -> String {
+    let mut text = record.args().to_string();
+    for (key, value) in text_fields(record) {
+        if value.contains(' ') {
+            text.push_str(&format!(" {}=\"{}\"", key, value));
+        } else {
+            text.push_str(&format!(" {}={}", key, value));
+        }
+    }
+    text
+}
+
+/// Encode a record in the journald native protocol, with the key-values of
+/// the log call as AUTODNS_* fields
+fn journald_entry(record: &Record) -> Vec<u8> {
+    let mut entry = Vec::new();
+    let mut field = |name: &str, value: &str| {
+        if value.contains('\n') {
+            // Binary-safe form: name, newline, little endian length, value
+            entry.extend_from_slice(name.as_bytes());
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            entry.extend_from_slice(value.as_bytes());
+            entry.push(b'\n');
+        } else {
+            entry.extend_from_slice(format!("{}={}\n", name, value).as_bytes());
+        }
+    };
+
+    field("MESSAGE", &record.args().to_string());
+    field("PRIORITY", &severity(record.level()).to_string());
+    field("SYSLOG_IDENTIFIER", IDENTIFIER);
+    field("CODE_MODULE", record.target());
+    if let Some(file) = record.file() {
+        field("CODE_FILE", file);
+    }
+    if let Some(line) = record.line() {
+        field("CODE_LINE", &line.to_string());
+    }
+
+    for (key, value) in text_fields(record) {
+        let name: String = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        field(&format!("AUTODNS_{}", name), &value);
+    }
+
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_journald_entry_length_prefixes_multi_line_values() {
+        let kvs = [("event", "server_down"), ("error", "line one\nline two")];
+        let entry = journald_entry(
+            &Record::builder()
+                .args(format_args!("DNS server is down"))
+                .level(Level::Warn)
+                .target("autodns::health")
+                .key_values(&kvs)
+                .build(),
+        );
+
+        let contains = |bytes: &[u8]| entry.windows(bytes.len()).any(|window| window == bytes);
+        assert!(contains(b"MESSAGE=DNS server is down\n"));
+        assert!(contains(b"PRIORITY=4\n"));
+        assert!(contains(b"SYSLOG_IDENTIFIER=autodns\n"));
+        assert!(contains(b"AUTODNS_EVENT=server_down\n"));
+
+        let mut multi_line = b"AUTODNS_ERROR\n".to_vec();
+        multi_line.extend_from_slice(&17u64.to_le_bytes());
+        multi_line.extend_from_slice(b"line one\nline two\n");
+        assert!(contains(&multi_line));
+        assert!(!contains(b"AUTODNS_ERROR="));
+    }
+
+    #[test]
+    fn test_priority_follows_level() {
+        let priority = |level| {
+            let entry = journald_entry(&Record::builder().args(format_args!("message")).level(level).build());
+            String::from_utf8(entry)
+                .unwrap()
+                .lines()
+                .find_map(|line| line.strip_prefix("PRIORITY=").map(str::to_string))
+        };
+
+        assert_eq!(priority(Level::Error).as_deref(), Some("3"));
+        assert_eq!(priority(Level::Warn).as_deref(), Some("4"));
+        assert_eq!(priority(Level::Info).as_deref(), Some("6"));
+        assert_eq!(priority(Level::Debug).as_deref(), Some("7"));
+        assert_eq!(priority(Level::Trace).as_deref(), Some("7"));
+    }
+
+    #[test]
+    fn test_syslog_line_uses_rfc3164_timestamp() {
+        let time = chrono::Local.with_ymd_and_hms(2024, 1, 5, 9, 3, 7).unwrap();
+
+        // Facility daemon (3), severity warning (4); days are padded with a space
+        assert_eq!(
+            syslog_line(Level::Warn, time, "DNS server is down"),
+            format!("<28>Jan  5 09:03:07 autodns[{}]: DNS server is down", std::process::id())
+        );
+    }
+
+    #[test]
+    fn test_syslog_text_quotes_values_with_spaces() {
+        let kvs = [("event", "selection_updated"), ("selection", "1.1.1.1 8.8.8.8")];
+        let text = syslog_text(
+            &Record::builder()
+                .args(format_args!("Selected 2 DNS servers"))
+                .level(Level::Info)
+                .key_values(&kvs)
+                .build(),
+        );
+
+        assert_eq!(
+            text,
+            "Selected 2 DNS servers event=selection_updated selection=\"1.1.1.1 8.8.8.8\""
+        );
+    }
+}
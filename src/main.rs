@@ -1,16 +1,30 @@
 mod config;
+mod control;
+mod daemon;
 mod dns_checker;
+mod health;
+mod hooks;
+mod logging;
+mod metrics;
+mod network;
+mod output;
+mod probe;
 mod resolv_conf;
+mod state;
+mod status;
+mod systemd;
+mod watcher;
+mod webhook;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use clap::{Parser, Subcommand};
 use config::{Config, OperationMode};
-use dns_checker::{select_best_dns, DnsChecker};
-use log::{error, info, warn};
+use dns_checker::{select_best_dns, select_first_online_dns, select_tiered_dns, DnsChecker};
+use log::{info, warn};
+use output::{display_benchmark_results_with_selection, display_check_results, OutputFormat, Report};
 use resolv_conf::ResolvConfManager;
 use std::net::IpAddr;
 use std::time::Duration;
-use tokio::time;
 
 #[derive(Parser)]
 #[command(name = "autodns")]
@@ -19,6 +33,18 @@ struct Cli {
     #[arg(short, long, default_value = "config.yaml")]
     config: String,
 
+    /// Log format, overrides log_format from the config
+    #[arg(long, value_enum, global = true)]
+    log_format: Option<logging::LogFormat>,
+
+    /// Where logs go, overrides log_target from the config
+    #[arg(long, value_enum, global = true)]
+    log_target: Option<logging::LogTarget>,
+
+    /// Print the resolv.conf changes as a diff instead of writing them (run, check and benchmark)
+    #[arg(long, global = true)]
+    dry_run: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -27,292 +53,637 @@ struct Cli {
 enum Commands {
     /// Run the Autodns daemon
     Run,
-    /// Run once based on config mode (check or benchmark) and exit
-    Check,
+    /// Run once based on config mode (check, benchmark or hybrid) and exit
+    Check {
+        /// How to print the results
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+    },
     /// Force benchmark mode once and exit (ignores config mode)
-    Benchmark,
+    Benchmark {
+        /// How to print the results
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Measure DNS servers without touching resolv.conf; needs neither root nor a valid configuration
+    Probe {
+        /// Servers to probe instead of those of the configuration
+        servers: Vec<IpAddr>,
+        /// Queries sent to each server
+        #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+        samples: u64,
+        /// Query timeout, instead of timeout_seconds from the configuration
+        #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+        timeout: Option<u64>,
+        /// How to print the results
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Show what the running daemon is doing
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Ask the running daemon to run a round now
+    Trigger,
+    /// Stop the running daemon from touching resolv.conf until resumed
+    Pause,
+    /// Let the running daemon manage resolv.conf again and run a round
+    Resume,
+    /// Always keep a server first in resolv.conf
+    Pin {
+        /// Name or address of a configured server
+        server: String,
+    },
+    /// Remove the pinned server
+    Unpin,
+    /// Keep a server out of resolv.conf
+    Exclude {
+        /// Name or address of a configured server
+        server: String,
+        /// Include the server again after this many seconds, at most a year
+        #[arg(
+            long = "for",
+            value_name = "SECONDS",
+            value_parser = clap::value_parser!(u64).range(..=control::MAX_EXCLUDE_SECONDS)
+        )]
+        duration_seconds: Option<u64>,
+    },
+    /// Allow an excluded server again
+    Include {
+        /// Name or address of a configured server
+        server: String,
+    },
+    /// Check the configuration and list every error and warning
+    Validate {
+        /// Print the diagnostics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print a hardened systemd unit for this configuration
+    SystemdUnit {
+        /// Run as a transient user with only the capabilities it needs
+        #[arg(long)]
+        dynamic_user: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-
     let cli = Cli::parse();
 
-    info!("Starting Autodns");
+    // The daemon commands would write resolv.conf regardless
+    if cli.dry_run
+        && !matches!(
+            cli.command,
+            Some(Commands::Run) | Some(Commands::Check { .. }) | Some(Commands::Benchmark { .. }) | None
+        )
+    {
+        bail!("--dry-run only applies to run, check and benchmark");
+    }
+
+    // These commands do not need a valid configuration
+    match cli.command {
+        Some(Commands::Validate { json }) => {
+            // Reports problems instead of failing on the first one
+            logging::init(cli.log_format.unwrap_or_default(), cli.log_target.unwrap_or_default());
+            return validate_config(&cli.config, json);
+        }
+        Some(Commands::Probe {
+            servers,
+            samples,
+            timeout,
+            output,
+        }) => {
+            logging::init(cli.log_format.unwrap_or_default(), cli.log_target.unwrap_or_default());
+            return probe_servers(&cli.config, servers, samples as usize, timeout, output).await;
+        }
+        _ => {}
+    }
+
+    // Load configuration first, it decides how to log
+    let mut config = Config::from_file(&cli.config)?;
+    config.log_format = cli.log_format.unwrap_or(config.log_format);
+    config.log_target = cli.log_target.unwrap_or(config.log_target);
+
+    logging::init(config.log_format, config.log_target);
 
-    // Load configuration
-    let config = Config::from_file(&cli.config)?;
+    info!("Starting Autodns");
     info!("Loaded configuration from {}", cli.config);
+    // Check the host only before probing and writing resolv.conf, not for every client command
+    let mut warnings = config.warnings();
+    if matches!(
+        cli.command,
+        Some(Commands::Run | Commands::Check { .. } | Commands::Benchmark { .. }) | None
+    ) {
+        let environment = config.environment_diagnostics();
+        config::ensure_no_errors(&environment)?;
+        warnings.extend(environment);
+    }
+    for warning in warnings {
+        warn!(event = "config_warning", field = warning.field.as_deref(); "{}", warning.message);
+    }
 
     match cli.command {
         Some(Commands::Run) | None => {
-            run_daemon(config).await?;
+            daemon::run_daemon(config, cli.dry_run).await?;
         }
-        Some(Commands::Check) => {
+        Some(Commands::Check { output }) => {
             // Run one-time check/benchmark based on config.mode
-            run_once(config).await?;
+            run_once(config, cli.dry_run, output).await?;
         }
-        Some(Commands::Benchmark) => {
+        Some(Commands::Benchmark { output }) => {
             // Force benchmark mode for this command
-            benchmark_once(config).await?;
+            config.mode = OperationMode::Benchmark;
+            benchmark_once(config, cli.dry_run, output).await?;
+        }
+        Some(Commands::Status { json }) => {
+            show_status(config, json).await?;
+        }
+        Some(Commands::Trigger) => {
+            send_control_request(config, control::Request::Trigger).await?;
+        }
+        Some(Commands::Pause) => {
+            send_control_request(config, control::Request::Pause).await?;
+        }
+        Some(Commands::Resume) => {
+            send_control_request(config, control::Request::Resume).await?;
+        }
+        Some(Commands::Pin { server }) => {
+            send_control_request(config, control::Request::Pin { server }).await?;
+        }
+        Some(Commands::Unpin) => {
+            send_control_request(config, control::Request::Unpin).await?;
+        }
+        Some(Commands::Exclude {
+            server,
+            duration_seconds,
+        }) => {
+            send_control_request(
+                config,
+                control::Request::Exclude {
+                    server,
+                    duration_seconds,
+                },
+            )
+            .await?;
+        }
+        Some(Commands::Include { server }) => {
+            send_control_request(config, control::Request::Include { server }).await?;
+        }
+        Some(Commands::Validate { .. }) | Some(Commands::Probe { .. }) => {
+            unreachable!("handled before loading the configuration")
+        }
+        Some(Commands::SystemdUnit { dynamic_user }) => {
+            print_systemd_unit(&config, &cli.config, dynamic_user)?;
         }
     }
 
     Ok(())
 }
 
-async fn run_daemon(config: Config) -> Result<()> {
-    info!("Running in daemon mode");
+async fn run_once(config: Config, dry_run: bool, output: OutputFormat) -> Result<()> {
+    info!("Running one-time operation in {:?} mode", config.mode);
 
-    let resolv_manager = ResolvConfManager::new(config.resolv_conf_path().to_string());
+    match config.mode {
+        OperationMode::FirstOnline => check_once(config, dry_run, output).await,
+        OperationMode::Benchmark | OperationMode::Hybrid => benchmark_once(config, dry_run, output).await,
+    }
+}
 
-    // Check permissions before starting
-    resolv_manager.check_permissions()?;
+async fn check_once(config: Config, dry_run: bool, output: OutputFormat) -> Result<()> {
+    info!("Running one-time health check");
+    ensure_connectivity(&config).await?;
 
     let checker = DnsChecker::new()
         .with_timeout(Duration::from_secs(config.timeout_seconds));
+    let servers = probe_list(&config);
 
-    let servers: Vec<_> = config
-        .dns_servers
-        .iter()
-        .map(|s| (s.address, s.name.clone()))
-        .collect();
+    let results = checker.check_multiple(&servers).await;
+    let table = output == OutputFormat::Table;
 
-    let mut execution_interval = time::interval(Duration::from_secs(config.execution_interval_seconds));
+    // Update resolv.conf with first 2 online servers
+    let ranked = select_first_online_dns(&results, &config.dns_servers, results.len());
+    let selected_dns = config.select(&ranked, config.selection_size());
 
-    // Run initial check/benchmark based on mode
-    match config.mode {
-        OperationMode::FirstOnline => {
-            info!("Running initial health check (FirstOnline mode)");
-            let results = checker.check_multiple(&servers).await;
-            display_check_results(&results);
-
-            // Update resolv.conf with first 2 online servers
-            let selected_dns = select_first_online_dns(&results, 2);
-            if !selected_dns.is_empty() {
-                if let Err(e) = resolv_manager.update_dns_servers(&selected_dns) {
-                    error!("Failed to update resolv.conf: {}", e);
-                } else {
-                    info!("Updated resolv.conf with first {} online DNS servers", selected_dns.len());
-                }
-            } else {
-                warn!("No online DNS servers found!");
-            }
-        }
-        OperationMode::Benchmark => {
-            info!("Running initial benchmark");
-            let results = checker.benchmark_multiple(&servers).await;
-
-            // Update resolv.conf with best servers by latency
-            let best_dns = select_best_dns(&results, 2);
-            display_benchmark_results_with_selection(&results, &best_dns);
-
-            if !best_dns.is_empty() {
-                if let Err(e) = resolv_manager.update_dns_servers(&best_dns) {
-                    error!("Failed to update resolv.conf: {}", e);
-                } else {
-                    info!("Updated resolv.conf with {} fastest DNS servers", best_dns.len());
+    if table {
+        display_check_results(&results);
+
+        let online_count = results.iter().filter(|r| r.is_online).count();
+        println!(
+            "\nSummary: {}/{} DNS servers are online",
+            online_count,
+            results.len()
+        );
+
+        if !selected_dns.is_empty() {
+            println!("\nSelected DNS servers (first {} online):", selected_dns.len());
+            for dns in &selected_dns {
+                if let Some(result) = results.iter().find(|r| r.address == *dns) {
+                    println!("  {} ({})", result.name, result.address);
                 }
-            } else {
-                warn!("No online DNS servers found!");
             }
+            display_pinned(&config);
         }
     }
 
-    loop {
-        execution_interval.tick().await;
+    let resolv_conf = write_selection(&config, &selected_dns, dry_run, table)?;
+    if table && resolv_conf.written {
+        println!("\nUpdated {} with selected DNS servers", config.resolv_conf_path());
+    }
+
+    let report = Report::new("check", config.timeout_seconds, results, resolv_conf);
+    output::print_report(&report, output)
+}
 
-        match config.mode {
-            OperationMode::FirstOnline => {
-                info!("Running scheduled health check (FirstOnline mode)");
-                let results = checker.check_multiple(&servers).await;
-                display_check_results(&results);
+async fn benchmark_once(config: Config, dry_run: bool, output: OutputFormat) -> Result<()> {
+    info!("Running one-time benchmark");
+    ensure_connectivity(&config).await?;
 
-                let selected_dns = select_first_online_dns(&results, 2);
-                if !selected_dns.is_empty() {
-                    if let Err(e) = resolv_manager.update_dns_servers(&selected_dns) {
-                        error!("Failed to update resolv.conf: {}", e);
-                    } else {
-                        info!("Updated resolv.conf with first {} online DNS servers", selected_dns.len());
-                    }
-                } else {
-                    error!("ALERT: All DNS servers are offline!");
-                }
+    let checker = DnsChecker::new()
+        .with_timeout(Duration::from_secs(config.timeout_seconds));
+    let servers = probe_list(&config);
+
+    let results = checker.benchmark_multiple(&servers).await;
+    let table = output == OutputFormat::Table;
+
+    // Hybrid mode ranks the most preferred healthy tier first
+    let ranked = match config.mode {
+        OperationMode::Hybrid => select_tiered_dns(&results, &config.dns_servers, config.selection_size()),
+        _ => select_best_dns(&results, &config.dns_servers, results.len()),
+    };
+    let best_dns = config.select(&ranked, config.selection_size());
+
+    if table {
+        display_benchmark_results_with_selection(&results, &best_dns);
+
+        if !best_dns.is_empty() {
+            match config.mode {
+                OperationMode::Hybrid => println!("\n✓ Selected DNS servers of the most preferred healthy tier:"),
+                _ => println!("\n✓ Selected {} fastest DNS servers:", best_dns.len()),
             }
-            OperationMode::Benchmark => {
-                info!("Running scheduled benchmark (Benchmark mode)");
-                let results = checker.benchmark_multiple(&servers).await;
-
-                let best_dns = select_best_dns(&results, 2);
-                display_benchmark_results_with_selection(&results, &best_dns);
-
-                if !best_dns.is_empty() {
-                    if let Err(e) = resolv_manager.update_dns_servers(&best_dns) {
-                        error!("Failed to update resolv.conf: {}", e);
-                    } else {
-                        info!("Updated resolv.conf with {} fastest DNS servers", best_dns.len());
+            for dns in &best_dns {
+                if let Some(result) = results.iter().find(|r| r.address == *dns) {
+                    if let Some(latency) = result.latency_ms {
+                        println!("  • {} ({}) - {:.2}ms", result.name, result.address, latency);
                     }
-                } else {
-                    warn!("No online DNS servers found in benchmark!");
                 }
             }
+            display_pinned(&config);
         }
     }
+
+    let resolv_conf = write_selection(&config, &best_dns, dry_run, table)?;
+    if table && resolv_conf.written {
+        println!("\n✓ Updated {} with fastest DNS servers", config.resolv_conf_path());
+    }
+
+    let report = Report::new(config.mode.name(), config.timeout_seconds, results, resolv_conf);
+    output::print_report(&report, output)
 }
 
-async fn run_once(config: Config) -> Result<()> {
-    info!("Running one-time operation in {:?} mode", config.mode);
+/// Probes from an isolated host would only blame the DNS servers
+async fn ensure_connectivity(config: &Config) -> Result<()> {
+    let servers: Vec<_> = config.dns_servers.iter().map(|server| server.address).collect();
+    if let network::Connectivity::NetworkUnavailable(reason) =
+        network::precheck(&config.connectivity, &servers).await
+    {
+        bail!("Network unavailable ({}), leaving {} alone", reason, config.resolv_conf_path());
+    }
+    Ok(())
+}
 
-    match config.mode {
-        OperationMode::FirstOnline => check_once(config).await,
-        OperationMode::Benchmark => benchmark_once(config).await,
+/// Pinned servers are written whatever the results
+fn display_pinned(config: &Config) {
+    let pinned: Vec<_> = config.dns_servers.iter().filter(|s| s.pinned).collect();
+    if pinned.is_empty() {
+        return;
+    }
+
+    println!("\nPinned DNS servers (always written first):");
+    for server in pinned {
+        println!("  {} ({})", server.name, server.address);
     }
 }
 
-async fn check_once(config: Config) -> Result<()> {
-    info!("Running one-time health check");
+/// Configured servers worth probing: those of the families the policy allows,
+/// without the IPv6 servers when this host cannot reach them
+fn probe_list(config: &Config) -> Vec<(IpAddr, String)> {
+    let ipv6_available = network::has_ipv6_default_route();
+    if !ipv6_available && config.dns_servers.iter().any(|s| s.address.is_ipv6()) {
+        info!("No IPv6 default route, skipping the IPv6 DNS servers");
+    }
 
-    let checker = DnsChecker::new()
-        .with_timeout(Duration::from_secs(config.timeout_seconds));
-    let servers: Vec<_> = config
+    config
         .dns_servers
         .iter()
+        .filter(|s| config.is_probed(s.address, ipv6_available))
         .map(|s| (s.address, s.name.clone()))
-        .collect();
-
-    let results = checker.check_multiple(&servers).await;
-    display_check_results(&results);
-
-    let online_count = results.iter().filter(|r| r.is_online).count();
-    println!(
-        "\nSummary: {}/{} DNS servers are online",
-        online_count,
-        results.len()
-    );
+        .collect()
+}
 
-    // Update resolv.conf with first 2 online servers
-    let selected_dns = select_first_online_dns(&results, 2);
-    if !selected_dns.is_empty() {
-        println!("\nSelected DNS servers (first {} online):", selected_dns.len());
-        for dns in &selected_dns {
-            if let Some(result) = results.iter().find(|r| r.address == *dns) {
-                println!("  {} ({})", result.name, result.address);
+/// Write the selected servers to resolv.conf, or only compute the changes in a
+/// dry run. The diff is printed with tables, and kept for structured output.
+/// Without a selection, the all-offline policy decides what to write.
+fn write_selection(
+    config: &Config,
+    selected: &[IpAddr],
+    dry_run: bool,
+    table: bool,
+) -> Result<output::ResolvConfReport> {
+    let mut report = output::ResolvConfReport {
+        path: config.resolv_conf_path().to_string(),
+        written: false,
+        dry_run,
+        nameservers: Vec::new(),
+        diff: None,
+    };
+
+    let fallback;
+    let selected = if selected.is_empty() {
+        match config.all_offline_servers() {
+            Some(servers) => {
+                warn!(
+                    "No online DNS servers found, writing {:?} (on_all_offline: {})",
+                    servers,
+                    config.on_all_offline.name()
+                );
+                fallback = servers;
+                &fallback[..]
+            }
+            None => {
+                warn!("No online DNS servers found!");
+                return Ok(report);
             }
         }
+    } else {
+        selected
+    };
 
-        let resolv_manager = ResolvConfManager::new(config.resolv_conf_path().to_string());
-        resolv_manager.check_permissions()?;
-        resolv_manager.update_dns_servers(&selected_dns)?;
-        println!("\nUpdated {} with selected DNS servers", config.resolv_conf_path());
+    let resolv_manager = ResolvConfManager::new(config.resolv_conf_path().to_string())
+        .with_dry_run(dry_run)
+        .with_pinned(config.pinned_servers());
+    resolv_manager.check_permissions()?;
+    report.nameservers = resolv_manager.nameservers(selected);
+
+    if dry_run && !table {
+        report.diff = Some(resolv_manager.diff(selected)?);
+        return Ok(report);
+    }
+
+    if dry_run {
+        println!("\nChanges to {} (dry run, not written):", config.resolv_conf_path());
+    }
+    resolv_manager.update_dns_servers(selected)?;
+    report.written = !dry_run;
+
+    Ok(report)
+}
+
+/// Probe the given servers, or those of the configuration, and print their latency.
+/// The configuration is only read, never validated, and resolv.conf is left alone.
+async fn probe_servers(
+    config_path: &str,
+    addresses: Vec<IpAddr>,
+    samples: usize,
+    timeout: Option<u64>,
+    output: OutputFormat,
+) -> Result<()> {
+    let config = if addresses.is_empty() {
+        Some(Config::parse(config_path).with_context(|| {
+            format!("No servers given and {} cannot be loaded", config_path)
+        })?)
+    } else {
+        Config::parse(config_path).ok()
+    };
+
+    let configured = config.as_ref().map_or(&[][..], |c| c.dns_servers.as_slice());
+    let servers: Vec<_> = if addresses.is_empty() {
+        configured.iter().map(|s| (s.address, s.name.clone())).collect()
     } else {
-        warn!("No online DNS servers found!");
+        // Known servers keep their configured name
+        addresses
+            .iter()
+            .map(|address| {
+                let name = configured
+                    .iter()
+                    .find(|s| s.address == *address)
+                    .map_or_else(|| address.to_string(), |s| s.name.clone());
+                (*address, name)
+            })
+            .collect()
+    };
+    if servers.is_empty() {
+        bail!("No DNS servers to probe");
     }
 
-    Ok(())
+    let timeout_seconds = timeout
+        .or(config.map(|c| c.timeout_seconds))
+        .filter(|seconds| *seconds > 0)
+        .unwrap_or(2);
+
+    info!(
+        "Probing {} DNS servers, {} queries each with a {} second timeout",
+        servers.len(),
+        samples,
+        timeout_seconds
+    );
+
+    let checker = DnsChecker::new().with_timeout(Duration::from_secs(timeout_seconds));
+    let results = probe::run(&checker, &servers, samples).await;
+    probe::print_results(&results, timeout_seconds, output)
 }
 
-async fn benchmark_once(config: Config) -> Result<()> {
-    info!("Running one-time benchmark");
+fn control_socket(config: &Config) -> Result<&str> {
+    match config.control_socket.as_deref() {
+        Some(socket) => Ok(socket),
+        None => bail!("control_socket is disabled in the configuration"),
+    }
+}
 
-    let checker = DnsChecker::new()
-        .with_timeout(Duration::from_secs(config.timeout_seconds));
-    let servers: Vec<_> = config
-        .dns_servers
-        .iter()
-        .map(|s| (s.address, s.name.clone()))
-        .collect();
+async fn show_status(config: Config, json: bool) -> Result<()> {
+    let socket = control_socket(&config)?;
 
-    let results = checker.benchmark_multiple(&servers).await;
+    match control::send_request(socket, &control::Request::Status).await? {
+        control::Response::Status(status) => {
+            if json {
+                println!("{}", serde_json::to_string_pretty(&status)?);
+            } else {
+                display_status(&status);
+            }
+            Ok(())
+        }
+        control::Response::Ok { .. } => bail!("The daemon sent an unexpected response"),
+        control::Response::Error { message } => bail!("The daemon reported an error: {}", message),
+    }
+}
 
-    let best_dns = select_best_dns(&results, 2);
-    display_benchmark_results_with_selection(&results, &best_dns);
+/// Send an action to the running daemon and print its answer
+async fn send_control_request(config: Config, request: control::Request) -> Result<()> {
+    let socket = control_socket(&config)?;
 
-    if !best_dns.is_empty() {
-        println!("\n✓ Selected 2 fastest DNS servers:");
-        for dns in &best_dns {
-            if let Some(result) = results.iter().find(|r| r.address == *dns) {
-                if let Some(latency) = result.latency_ms {
-                    println!("  • {} ({}) - {:.2}ms", result.name, result.address, latency);
-                }
+    match control::send_request(socket, &request).await? {
+        control::Response::Ok { message } => {
+            println!("✓ {}", message);
+            Ok(())
+        }
+        control::Response::Status(_) => bail!("The daemon sent an unexpected response"),
+        control::Response::Error { message } => bail!("{}", message),
+    }
+}
+
+/// Print the diagnostics of the configuration, exiting with 1 if it has errors
+fn validate_config(path: &str, json: bool) -> Result<()> {
+    let diagnostics = match Config::parse(path) {
+        Ok(config) => config.diagnostics(),
+        Err(e) => vec![config::Diagnostic::error(None, format!("{:#}", e))],
+    };
+
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == config::Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+
+    if json {
+        let report = serde_json::json!({
+            "config": path,
+            "valid": errors == 0,
+            "errors": errors,
+            "warnings": warnings,
+            "diagnostics": diagnostics,
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("\n=== Configuration {} ===", path);
+        for diagnostic in &diagnostics {
+            let marker = match diagnostic.severity {
+                config::Severity::Error => "✗ error  ",
+                config::Severity::Warning => "⚠ warning",
+            };
+            match &diagnostic.field {
+                Some(field) => println!("{} [{}] {}", marker, field, diagnostic.message),
+                None => println!("{} {}", marker, diagnostic.message),
             }
         }
+        if errors == 0 {
+            println!("\n✓ Valid configuration ({})", plural(warnings, "warning"));
+        } else {
+            println!("\n✗ {}, {}", plural(errors, "error"), plural(warnings, "warning"));
+        }
+    }
 
-        let resolv_manager = ResolvConfManager::new(config.resolv_conf_path().to_string());
-        resolv_manager.check_permissions()?;
-        resolv_manager.update_dns_servers(&best_dns)?;
-        println!("\n✓ Updated {} with fastest DNS servers", config.resolv_conf_path());
+    if errors > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("1 {}", noun)
     } else {
-        warn!("No online DNS servers found!");
+        format!("{} {}s", count, noun)
     }
+}
 
+fn print_systemd_unit(config: &Config, config_path: &str, dynamic_user: bool) -> Result<()> {
+    let config_path = std::fs::canonicalize(config_path)
+        .with_context(|| format!("Failed to resolve {}", config_path))?;
+    let binary = std::env::current_exe().context("Failed to locate the autodns binary")?;
+
+    print!("{}", systemd::render_unit(config, &config_path, &binary, dynamic_user)?);
     Ok(())
 }
 
-fn display_check_results(results: &[dns_checker::DnsCheckResult]) {
-    println!("\n=== DNS Health Check Results ===");
-    for result in results {
-        let status = if result.is_online {
-            "ONLINE"
-        } else {
-            "OFFLINE"
-        };
+fn display_status(status: &status::DaemonStatus) {
+    let now = status::unix_now();
+
+    println!("\n=== Autodns Status ===");
+    println!("Version:          {}", status.version);
+    println!("Mode:             {}", status.mode);
+    println!("Uptime:           {}", format_duration(status.uptime_seconds));
+    match status.last_round_at {
+        Some(at) => println!(
+            "Last run:         {} ({} ago)",
+            format_timestamp(at),
+            format_duration(now.saturating_sub(at))
+        ),
+        None => println!("Last run:         never"),
+    }
+    if let Some(at) = status.next_round_at {
         println!(
-            "{:15} ({:40}) - {}",
-            result.name,
-            result.address.to_string(),
-            status
+            "Next run:         {} (in {})",
+            format_timestamp(at),
+            format_duration(at.saturating_sub(now))
         );
     }
-}
+    println!("External changes: {}", status.external_changes);
+    if let Some(reason) = &status.network_unavailable {
+        println!("Network:          unavailable ({}), rounds are skipped", reason);
+    }
+    if status.paused {
+        println!("Paused:           yes, resolv.conf is not being updated");
+    }
+    if let Some(pinned) = &status.pinned {
+        println!("Pinned:           {} ({})", pinned.name, pinned.address);
+    }
+    for excluded in &status.excluded {
+        let until = match excluded.until {
+            Some(until) => format!("for {}", format_duration(until.saturating_sub(now))),
+            None => "until included".to_string(),
+        };
+        println!("Excluded:         {} ({}) {}", excluded.name, excluded.address, until);
+    }
 
+    println!("\nSelected DNS servers:");
+    if status.selection.is_empty() {
+        println!("  (none)");
+    }
+    for server in &status.selection {
+        println!("  → {} ({})", server.name, server.address);
+    }
 
-fn display_benchmark_results_with_selection(
-    results: &[dns_checker::DnsCheckResult],
-    selected: &[IpAddr],
-) {
-    println!("\n=== DNS Benchmark Results ===");
-
-    // Sort results by latency for display
-    let mut sorted_results: Vec<_> = results.iter().collect();
-    sorted_results.sort_by(|a, b| {
-        match (a.latency_ms, b.latency_ms) {
-            (Some(lat_a), Some(lat_b)) => lat_a.partial_cmp(&lat_b).unwrap_or(std::cmp::Ordering::Equal),
-            (Some(_), None) => std::cmp::Ordering::Less,
-            (None, Some(_)) => std::cmp::Ordering::Greater,
-            (None, None) => std::cmp::Ordering::Equal,
-        }
-    });
-
-    for result in sorted_results {
-        let is_selected = selected.contains(&result.address);
-        let marker = if is_selected { "→" } else { " " };
-
-        if let Some(latency) = result.latency_ms {
-            println!(
-                "{} {:15} ({:40}) - {:.2}ms",
-                marker,
-                result.name,
-                result.address.to_string(),
-                latency
-            );
-        } else {
-            println!(
-                "{} {:15} ({:40}) - FAILED",
-                marker,
-                result.name,
-                result.address.to_string()
-            );
+    println!("\nDNS servers:");
+    for server in &status.servers {
+        let state = format!("{:?}", server.state).to_uppercase();
+        let latency = match (server.last_latency_ms, server.smoothed_latency_ms) {
+            (Some(last), Some(smoothed)) => format!("{:.2}ms (smoothed {:.2}ms)", last, smoothed),
+            (None, Some(smoothed)) => format!("smoothed {:.2}ms", smoothed),
+            _ => String::new(),
+        };
+        println!(
+            "  {:15} ({:40}) - {:7} loss {:3.0}% {}",
+            server.name,
+            server.address.to_string(),
+            state,
+            server.loss_ratio * 100.0,
+            latency
+        );
+    }
+
+    if !status.recent_errors.is_empty() {
+        println!("\nRecent errors:");
+        for entry in &status.recent_errors {
+            println!("  {} {}", format_timestamp(entry.at), entry.message);
         }
     }
 }
 
-/// Select first N online DNS servers from the list (in order)
-fn select_first_online_dns(results: &[dns_checker::DnsCheckResult], count: usize) -> Vec<IpAddr> {
-    results
-        .iter()
-        .filter(|r| r.is_online)
-        .take(count)
-        .map(|r| r.address)
-        .collect()
+fn format_timestamp(unix_seconds: u64) -> String {
+    chrono::DateTime::from_timestamp(unix_seconds as i64, 0)
+        .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| unix_seconds.to_string())
+}
+
+fn format_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
 }
@@ -0,0 +1,368 @@
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Upper bounds of the latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0,
+];
+
+/// Largest request we are willing to read before answering
+const MAX_REQUEST_SIZE: usize = 8192;
+
+pub type SharedMetrics = Arc<Mutex<Metrics>>;
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Cumulative count per bucket of LATENCY_BUCKETS
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct ServerMetrics {
+    name: String,
+    up: bool,
+    loss_ratio: f64,
+    latency: Histogram,
+}
+
+/// Daemon metrics exported in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    servers: BTreeMap<IpAddr, ServerMetrics>,
+    selection: Vec<(IpAddr, String)>,
+    resolv_conf_writes: u64,
+    resolv_conf_write_failures: u64,
+    external_changes: u64,
+    skipped_rounds: u64,
+    /// Unix timestamp of the last round that wrote a selection
+    last_successful_round: Option<f64>,
+}
+
+impl Metrics {
+    pub fn shared() -> SharedMetrics {
+        Arc::new(Mutex::new(Self::default()))
+    }
+
+    /// Record the latency of a successful probe
+    pub fn observe_latency(&mut self, address: IpAddr, name: &str, latency_ms: f64) {
+        let server = self.server(address, name);
+        server.latency.observe(latency_ms / 1000.0);
+    }
+
+    pub fn set_server_health(&mut self, address: IpAddr, name: &str, up: bool, loss_ratio: f64) {
+        let server = self.server(address, name);
+        server.up = up;
+        server.loss_ratio = loss_ratio;
+    }
+
+    pub fn set_selection(&mut self, selection: Vec<(IpAddr, String)>) {
+        self.selection = selection;
+    }
+
+    pub fn record_write(&mut self, success: bool) {
+        if success {
+            self.resolv_conf_writes += 1;
+        } else {
+            self.resolv_conf_write_failures += 1;
+        }
+    }
+
+    pub fn record_external_change(&mut self) {
+        self.external_changes += 1;
+    }
+
+    pub fn record_skipped_round(&mut self) {
+        self.skipped_rounds += 1;
+    }
+
+    pub fn record_successful_round(&mut self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        self.last_successful_round = Some(now);
+    }
+
+    fn server(&mut self, address: IpAddr, name: &str) -> &mut ServerMetrics {
+        let server = self.servers.entry(address).or_default();
+        if server.name != name {
+            server.name = name.to_string();
+        }
+        server
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP autodns_server_up Whether the DNS server is considered up (1) or down (0).\n");
+        out.push_str("# TYPE autodns_server_up gauge\n");
+        for (address, server) in &self.servers {
+            let _ = writeln!(
+                out,
+                "autodns_server_up{{{}}} {}",
+                server_labels(&server.name, address),
+                u8::from(server.up)
+            );
+        }
+
+        out.push_str("# HELP autodns_servers_up Number of configured DNS servers considered up.\n");
+        out.push_str("# TYPE autodns_servers_up gauge\n");
+        let _ = writeln!(
+            out,
+            "autodns_servers_up {}",
+            self.servers.values().filter(|s| s.up).count()
+        );
+
+        out.push_str("# HELP autodns_server_loss_ratio Share of failed probes in the recent history of the DNS server.\n");
+        out.push_str("# TYPE autodns_server_loss_ratio gauge\n");
+        for (address, server) in &self.servers {
+            let _ = writeln!(
+                out,
+                "autodns_server_loss_ratio{{{}}} {}",
+                server_labels(&server.name, address),
+                server.loss_ratio
+            );
+        }
+
+        out.push_str("# HELP autodns_server_latency_seconds Latency of successful probes to the DNS server.\n");
+        out.push_str("# TYPE autodns_server_latency_seconds histogram\n");
+        for (address, server) in &self.servers {
+            let labels = server_labels(&server.name, address);
+            for (count, bound) in server.latency.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "autodns_server_latency_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "autodns_server_latency_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, server.latency.count
+            );
+            let _ = writeln!(out, "autodns_server_latency_seconds_sum{{{}}} {}", labels, server.latency.sum);
+            let _ = writeln!(out, "autodns_server_latency_seconds_count{{{}}} {}", labels, server.latency.count);
+        }
+
+        out.push_str("# HELP autodns_selected_server_info DNS servers currently written to resolv.conf.\n");
+        out.push_str("# TYPE autodns_selected_server_info gauge\n");
+        for (position, (address, name)) in self.selection.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "autodns_selected_server_info{{{},position=\"{}\"}} 1",
+                server_labels(name, address),
+                position + 1
+            );
+        }
+
+        out.push_str("# HELP autodns_resolv_conf_writes_total Successful writes of resolv.conf.\n");
+        out.push_str("# TYPE autodns_resolv_conf_writes_total counter\n");
+        let _ = writeln!(out, "autodns_resolv_conf_writes_total {}", self.resolv_conf_writes);
+
+        out.push_str("# HELP autodns_resolv_conf_write_failures_total Failed writes of resolv.conf.\n");
+        out.push_str("# TYPE autodns_resolv_conf_write_failures_total counter\n");
+        let _ = writeln!(
+            out,
+            "autodns_resolv_conf_write_failures_total {}",
+            self.resolv_conf_write_failures
+        );
+
+        out.push_str("# HELP autodns_resolv_conf_external_changes_total Modifications of resolv.conf by other tools.\n");
+        out.push_str("# TYPE autodns_resolv_conf_external_changes_total counter\n");
+        let _ = writeln!(
+            out,
+            "autodns_resolv_conf_external_changes_total {}",
+            self.external_changes
+        );
+
+        out.push_str("# HELP autodns_rounds_skipped_total Rounds skipped because the host had no network connectivity.\n");
+        out.push_str("# TYPE autodns_rounds_skipped_total counter\n");
+        let _ = writeln!(out, "autodns_rounds_skipped_total {}", self.skipped_rounds);
+
+        if let Some(timestamp) = self.last_successful_round {
+            out.push_str("# HELP autodns_last_successful_round_timestamp_seconds Unix time of the last round that selected online DNS servers.\n");
+            out.push_str("# TYPE autodns_last_successful_round_timestamp_seconds gauge\n");
+            let _ = writeln!(out, "autodns_last_successful_round_timestamp_seconds {}", timestamp);
+        }
+
+        out
+    }
+}
+
+fn server_labels(name: &str, address: &IpAddr) -> String {
+    format!("server=\"{}\",address=\"{}\"", escape_label(name), address)
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Bind the metrics listener, failing early if the address is unusable
+pub async fn bind(listen: SocketAddr) -> Result<TcpListener> {
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to bind metrics listener on {}", listen))?;
+    info!("Serving Prometheus metrics on http://{}/metrics", listen);
+    Ok(listener)
+}
+
+/// Answer scrapes until the daemon exits
+pub async fn serve(listener: TcpListener, metrics: SharedMetrics) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, metrics).await {
+                        debug!("Metrics request from {} failed: {:#}", peer, e);
+                    }
+                });
+            }
+            Err(e) => warn!("Failed to accept metrics connection: {}", e),
+        }
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, metrics: SharedMetrics) -> Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+
+    // Read until the end of the headers; we never need a body
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+        if request.len() > MAX_REQUEST_SIZE {
+            anyhow::bail!("request too large");
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.lines().next().unwrap_or("").split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => {
+            let body = metrics.lock().map(|m| m.render()).unwrap_or_default();
+            ("200 OK", "text/plain; version=0.0.4; charset=utf-8", body)
+        }
+        ("GET", _) => ("404 Not Found", "text/plain", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "Method Not Allowed\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Metrics of a round that found one server up and wrote it
+    fn after_round() -> Metrics {
+        let mut metrics = Metrics::default();
+        let up: IpAddr = "192.0.2.1".parse().unwrap();
+        let down: IpAddr = "192.0.2.2".parse().unwrap();
+
+        metrics.observe_latency(up, "Lab", 3.0);
+        metrics.observe_latency(up, "Lab", 30.0);
+        metrics.set_server_health(up, "Lab", true, 0.0);
+        metrics.set_server_health(down, "Lab \"backup\"\\\n2", false, 0.5);
+        metrics.set_selection(vec![(up, "Lab".to_string())]);
+        metrics.record_write(true);
+        metrics.record_successful_round();
+        metrics
+    }
+
+    #[test]
+    fn test_every_metric_has_help_and_type() {
+        let out = after_round().render();
+
+        let families: Vec<_> = out
+            .lines()
+            .filter_map(|line| line.strip_prefix("# TYPE "))
+            .map(|line| line.split_whitespace().next().unwrap())
+            .collect();
+        assert!(families.contains(&"autodns_last_successful_round_timestamp_seconds"));
+
+        for line in out.lines().filter(|line| !line.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            let family = families
+                .iter()
+                .find(|family| {
+                    name == **family
+                        || ["_bucket", "_sum", "_count"]
+                            .iter()
+                            .any(|suffix| name.strip_suffix(suffix) == Some(**family))
+                })
+                .unwrap_or_else(|| panic!("{} has no TYPE line", name));
+            assert!(out.contains(&format!("# HELP {} ", family)), "{} has no HELP line", family);
+        }
+    }
+
+    #[test]
+    fn test_label_values_are_escaped() {
+        let out = after_round().render();
+
+        assert!(out.contains(r#"autodns_server_up{server="Lab \"backup\"\\\n2",address="192.0.2.2"} 0"#));
+        // Every sample stays on its own line
+        assert!(out.lines().all(|line| line.starts_with('#') || line.starts_with("autodns_")));
+    }
+
+    #[test]
+    fn test_gauges_after_successful_round() {
+        let out = after_round().render();
+
+        assert!(out.contains("autodns_server_up{server=\"Lab\",address=\"192.0.2.1\"} 1\n"));
+        assert!(out.contains("autodns_servers_up 1\n"));
+        assert!(out.contains("autodns_server_loss_ratio{server=\"Lab\",address=\"192.0.2.1\"} 0\n"));
+        assert!(out.contains(
+            "autodns_selected_server_info{server=\"Lab\",address=\"192.0.2.1\",position=\"1\"} 1\n"
+        ));
+        assert!(out.contains("autodns_resolv_conf_writes_total 1\n"));
+        assert!(out.contains("autodns_server_latency_seconds_bucket{server=\"Lab\",address=\"192.0.2.1\",le=\"0.005\"} 1\n"));
+        assert!(out.contains("autodns_server_latency_seconds_bucket{server=\"Lab\",address=\"192.0.2.1\",le=\"+Inf\"} 2\n"));
+        assert!(out.contains("autodns_server_latency_seconds_count{server=\"Lab\",address=\"192.0.2.1\"} 2\n"));
+        assert!(out.contains("autodns_last_successful_round_timestamp_seconds "));
+
+        // No round has succeeded yet
+        assert!(!Metrics::default().render().contains("autodns_last_successful_round_timestamp_seconds"));
+    }
+}
@@ -0,0 +1,119 @@
+use crate::config::ConnectivityConfig;
+use futures::future::join_all;
+use std::fs;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/// IPv4 routing table of the kernel
+const IPV4_ROUTES: &str = "/proc/net/route";
+
+/// IPv6 routing table of the kernel, absent when IPv6 is disabled
+const IPV6_ROUTES: &str = "/proc/net/ipv6_route";
+
+/// Route flags of usable routes, and of unreachable/prohibit routes
+const RTF_UP: u32 = 0x0001;
+const RTF_REJECT: u32 = 0x0200;
+
+/// Outcome of the connectivity precheck of a round
+#[derive(Debug, Clone, PartialEq)]
+pub enum Connectivity {
+    Available,
+    /// The host itself is isolated, so the probes would say nothing about the servers
+    NetworkUnavailable(String),
+}
+
+/// Check that the host can reach the outside world at all: it has a default route
+/// or a route to one of the DNS servers, and one of the configured targets accepts
+/// (or refuses) a TCP connection
+pub async fn precheck(config: &ConnectivityConfig, servers: &[IpAddr]) -> Connectivity {
+    if !config.enabled {
+        return Connectivity::Available;
+    }
+
+    // Air-gapped hosts and on-link resolvers need no default route
+    if !has_ipv4_default_route()
+        && !has_ipv6_default_route()
+        && !servers.iter().any(|server| has_route_to(*server))
+    {
+        return Connectivity::NetworkUnavailable("no route to the network or any DNS server".to_string());
+    }
+
+    if config.targets.is_empty() {
+        return Connectivity::Available;
+    }
+
+    let limit = Duration::from_secs(config.timeout_seconds);
+    let attempts = config.targets.iter().map(|target| async move {
+        // A refused connection still proves the target was reached
+        match timeout(limit, TcpStream::connect(target)).await {
+            Ok(Ok(_)) => true,
+            Ok(Err(e)) => e.kind() == ErrorKind::ConnectionRefused,
+            Err(_) => false,
+        }
+    });
+
+    if join_all(attempts).await.into_iter().any(|reached| reached) {
+        Connectivity::Available
+    } else {
+        let targets: Vec<_> = config.targets.iter().map(|target| target.to_string()).collect();
+        Connectivity::NetworkUnavailable(format!("none of {} is reachable", targets.join(", ")))
+    }
+}
+
+/// Whether the host has an IPv4 default route; assumed when the routing table
+/// cannot be read
+pub fn has_ipv4_default_route() -> bool {
+    let Ok(routes) = fs::read_to_string(IPV4_ROUTES) else {
+        return true;
+    };
+
+    // interface, destination, gateway, flags, ... (after a header line)
+    routes.lines().skip(1).any(|route| {
+        let fields: Vec<&str> = route.split_whitespace().collect();
+        if fields.len() < 4 {
+            return false;
+        }
+        let flags = u32::from_str_radix(fields[3], 16).unwrap_or(0);
+        fields[1] == "00000000" && flags & RTF_UP != 0 && flags & RTF_REJECT == 0 && fields[0] != "lo"
+    })
+}
+
+/// Whether the kernel has a route to an address; connecting a UDP socket only
+/// looks the route up, nothing is sent
+fn has_route_to(address: IpAddr) -> bool {
+    let local: SocketAddr = match address {
+        IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    UdpSocket::bind(local)
+        .and_then(|socket| socket.connect((address, 53)))
+        .is_ok()
+}
+
+/// Whether the host has a usable IPv6 default route, i.e. IPv6 servers can be reached
+pub fn has_ipv6_default_route() -> bool {
+    let Ok(routes) = fs::read_to_string(IPV6_ROUTES) else {
+        return false;
+    };
+
+    // destination, prefix length, source, source prefix length, next hop,
+    // metric, reference count, use count, flags, device
+    routes.lines().any(|route| {
+        let fields: Vec<&str> = route.split_whitespace().collect();
+        if fields.len() < 10 {
+            return false;
+        }
+        let default = fields[0].chars().all(|c| c == '0') && fields[1] == "00";
+        let flags = u32::from_str_radix(fields[8], 16).unwrap_or(RTF_REJECT);
+        default && flags & RTF_REJECT == 0 && fields[9] != "lo"
+    })
+}
+
+pub fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .unwrap_or_else(|_| "unknown host".to_string())
+}
@@ -0,0 +1,229 @@
+use crate::dns_checker::DnsCheckResult;
+use crate::network;
+use crate::status::unix_now;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::net::IpAddr;
+
+/// How `check`, `benchmark` and `probe` print their results
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Table,  // Human readable tables
+    Json,   // The whole report as one JSON document
+    Csv,    // One row per server, with a header
+    Yaml,   // The whole report as YAML
+}
+
+/// Results of a one-time check or benchmark, meant to be collected from many
+/// hosts and aggregated
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// `check`, `benchmark` or `hybrid`
+    pub command: &'static str,
+    pub host: String,
+    /// Unix timestamp, in seconds
+    pub timestamp: u64,
+    pub timeout_seconds: u64,
+    /// One entry per configured server, in configuration order
+    pub results: Vec<ServerReport>,
+    pub summary: Summary,
+    pub selection: Vec<SelectedServer>,
+    pub resolv_conf: ResolvConfReport,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServerReport {
+    #[serde(flatten)]
+    pub result: DnsCheckResult,
+    /// Position in resolv.conf, starting at 1, if the server was selected
+    pub selected: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub total: usize,
+    pub online: usize,
+    pub offline: usize,
+    /// Latency statistics of the servers that answered, benchmark only
+    pub min_latency_ms: Option<f64>,
+    pub avg_latency_ms: Option<f64>,
+    pub max_latency_ms: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SelectedServer {
+    pub name: String,
+    pub address: IpAddr,
+}
+
+/// What happened to resolv.conf
+#[derive(Debug, Serialize)]
+pub struct ResolvConfReport {
+    pub path: String,
+    pub written: bool,
+    pub dry_run: bool,
+    /// Nameservers of the new resolv.conf, pinned servers first; empty when
+    /// nothing was selected
+    pub nameservers: Vec<IpAddr>,
+    /// Changes that would have been written, in a dry run
+    pub diff: Option<String>,
+}
+
+impl Report {
+    pub fn new(
+        command: &'static str,
+        timeout_seconds: u64,
+        results: Vec<DnsCheckResult>,
+        resolv_conf: ResolvConfReport,
+    ) -> Self {
+        let selected = &resolv_conf.nameservers;
+        let latencies: Vec<f64> = results.iter().filter_map(|r| r.latency_ms).collect();
+        let online = results.iter().filter(|r| r.is_online).count();
+
+        let summary = Summary {
+            total: results.len(),
+            online,
+            offline: results.len() - online,
+            min_latency_ms: latencies.iter().copied().reduce(f64::min),
+            avg_latency_ms: (!latencies.is_empty())
+                .then(|| latencies.iter().sum::<f64>() / latencies.len() as f64),
+            max_latency_ms: latencies.iter().copied().reduce(f64::max),
+        };
+
+        let selection = selected
+            .iter()
+            .filter_map(|address| results.iter().find(|r| r.address == *address))
+            .map(|r| SelectedServer {
+                name: r.name.clone(),
+                address: r.address,
+            })
+            .collect();
+
+        let results = results
+            .into_iter()
+            .map(|result| ServerReport {
+                selected: selected.iter().position(|a| *a == result.address).map(|i| i + 1),
+                result,
+            })
+            .collect();
+
+        Self {
+            command,
+            host: network::hostname(),
+            timestamp: unix_now(),
+            timeout_seconds,
+            results,
+            summary,
+            selection,
+            resolv_conf,
+        }
+    }
+}
+
+/// A CSV row: one server, with the context needed to aggregate rows from many runs
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    host: &'a str,
+    timestamp: u64,
+    command: &'a str,
+    name: &'a str,
+    address: IpAddr,
+    is_online: bool,
+    latency_ms: Option<f64>,
+    error: Option<&'a str>,
+    selected: Option<usize>,
+}
+
+/// Print the report in a structured format; tables are printed as the command runs
+pub fn print_report(report: &Report, format: OutputFormat) -> Result<()> {
+    let rows = report.results.iter().map(|server| CsvRow {
+        host: &report.host,
+        timestamp: report.timestamp,
+        command: report.command,
+        name: &server.result.name,
+        address: server.result.address,
+        is_online: server.result.is_online,
+        latency_ms: server.result.latency_ms,
+        error: server.result.error.as_deref(),
+        selected: server.selected,
+    });
+    print_structured(report, rows, format)
+}
+
+/// Print a document as JSON or YAML, or its rows as CSV. Nothing is printed for
+/// tables, which each command prints its own way.
+pub fn print_structured<R: Serialize>(
+    document: &impl Serialize,
+    rows: impl IntoIterator<Item = R>,
+    format: OutputFormat,
+) -> Result<()> {
+    match format {
+        OutputFormat::Table => {}
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(document)?),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(document)?),
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush().context("Failed to write CSV")?;
+        }
+    }
+    Ok(())
+}
+
+pub fn display_check_results(results: &[DnsCheckResult]) {
+    println!("\n=== DNS Health Check Results ===");
+    for result in results {
+        let status = if result.is_online {
+            "ONLINE"
+        } else {
+            "OFFLINE"
+        };
+        println!(
+            "{:15} ({:40}) - {}",
+            result.name,
+            result.address.to_string(),
+            status
+        );
+    }
+}
+
+pub fn display_benchmark_results_with_selection(results: &[DnsCheckResult], selected: &[IpAddr]) {
+    println!("\n=== DNS Benchmark Results ===");
+
+    // Sort results by latency for display
+    let mut sorted_results: Vec<_> = results.iter().collect();
+    sorted_results.sort_by(|a, b| {
+        match (a.latency_ms, b.latency_ms) {
+            (Some(lat_a), Some(lat_b)) => lat_a.partial_cmp(&lat_b).unwrap_or(std::cmp::Ordering::Equal),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        }
+    });
+
+    for result in sorted_results {
+        let is_selected = selected.contains(&result.address);
+        let marker = if is_selected { "→" } else { " " };
+
+        if let Some(latency) = result.latency_ms {
+            println!(
+                "{} {:15} ({:40}) - {:.2}ms",
+                marker,
+                result.name,
+                result.address.to_string(),
+                latency
+            );
+        } else {
+            println!(
+                "{} {:15} ({:40}) - FAILED",
+                marker,
+                result.name,
+                result.address.to_string()
+            );
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use crate::dns_checker::DnsChecker;
+use crate::network;
+use crate::output::{self, OutputFormat};
+use crate::status::unix_now;
+use anyhow::Result;
+use futures::future::join_all;
+use serde::Serialize;
+use std::net::IpAddr;
+
+/// Latency statistics of one server over all samples
+#[derive(Debug, Serialize)]
+pub struct ProbeStats {
+    pub name: String,
+    pub address: IpAddr,
+    pub samples: usize,
+    pub answered: usize,
+    /// Share of the samples without an answer, from 0 to 1
+    pub loss_ratio: f64,
+    pub min_latency_ms: Option<f64>,
+    pub avg_latency_ms: Option<f64>,
+    pub median_latency_ms: Option<f64>,
+    pub p95_latency_ms: Option<f64>,
+    pub max_latency_ms: Option<f64>,
+    /// Error of the last sample that failed
+    pub last_error: Option<String>,
+}
+
+impl ProbeStats {
+    fn new(name: String, address: IpAddr, mut latencies: Vec<f64>, failures: Vec<String>) -> Self {
+        latencies.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+        let answered = latencies.len();
+        let samples = answered + failures.len();
+        // Nearest rank percentile of the sorted latencies
+        let percentile = |p: f64| {
+            let rank = ((p * answered as f64).ceil() as usize).max(1);
+            latencies.get(rank - 1).copied()
+        };
+
+        Self {
+            name,
+            address,
+            samples,
+            answered,
+            loss_ratio: if samples == 0 { 0.0 } else { failures.len() as f64 / samples as f64 },
+            min_latency_ms: latencies.first().copied(),
+            avg_latency_ms: (answered > 0).then(|| latencies.iter().sum::<f64>() / answered as f64),
+            median_latency_ms: percentile(0.5),
+            p95_latency_ms: percentile(0.95),
+            max_latency_ms: latencies.last().copied(),
+            last_error: failures.last().cloned(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ProbeReport<'a> {
+    host: String,
+    /// Unix timestamp, in seconds
+    timestamp: u64,
+    samples: usize,
+    timeout_seconds: u64,
+    results: &'a [ProbeStats],
+}
+
+/// Query every server `samples` times, all servers in parallel for each sample,
+/// and collect their latency statistics
+pub async fn run(checker: &DnsChecker, servers: &[(IpAddr, String)], samples: usize) -> Vec<ProbeStats> {
+    let mut latencies = vec![Vec::new(); servers.len()];
+    let mut failures = vec![Vec::new(); servers.len()];
+
+    for _ in 0..samples {
+        let answers = join_all(servers.iter().map(|(address, _)| checker.measure(*address))).await;
+        for (i, answer) in answers.into_iter().enumerate() {
+            match answer {
+                Ok(latency) => latencies[i].push(latency),
+                Err(e) => failures[i].push(e.to_string()),
+            }
+        }
+    }
+
+    servers
+        .iter()
+        .zip(latencies.into_iter().zip(failures))
+        .map(|((address, name), (latencies, failures))| {
+            ProbeStats::new(name.clone(), *address, latencies, failures)
+        })
+        .collect()
+}
+
+/// A CSV row, with the context needed to aggregate rows from many hosts
+#[derive(Serialize)]
+struct ProbeRow<'a> {
+    host: &'a str,
+    timestamp: u64,
+    name: &'a str,
+    address: IpAddr,
+    samples: usize,
+    answered: usize,
+    loss_ratio: f64,
+    min_latency_ms: Option<f64>,
+    avg_latency_ms: Option<f64>,
+    median_latency_ms: Option<f64>,
+    p95_latency_ms: Option<f64>,
+    max_latency_ms: Option<f64>,
+    last_error: Option<&'a str>,
+}
+
+pub fn print_results(results: &[ProbeStats], timeout_seconds: u64, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Table {
+        display_probe_results(results);
+        return Ok(());
+    }
+
+    let report = ProbeReport {
+        host: network::hostname(),
+        timestamp: unix_now(),
+        samples: results.first().map_or(0, |r| r.samples),
+        timeout_seconds,
+        results,
+    };
+    let rows = results.iter().map(|r| ProbeRow {
+        host: &report.host,
+        timestamp: report.timestamp,
+        name: &r.name,
+        address: r.address,
+        samples: r.samples,
+        answered: r.answered,
+        loss_ratio: r.loss_ratio,
+        min_latency_ms: r.min_latency_ms,
+        avg_latency_ms: r.avg_latency_ms,
+        median_latency_ms: r.median_latency_ms,
+        p95_latency_ms: r.p95_latency_ms,
+        max_latency_ms: r.max_latency_ms,
+        last_error: r.last_error.as_deref(),
+    });
+    output::print_structured(&report, rows, format)
+}
+
+fn display_probe_results(results: &[ProbeStats]) {
+    println!("\n=== DNS Probe Results ===");
+
+    // Fastest first, servers that never answered last
+    let mut sorted_results: Vec<_> = results.iter().collect();
+    sorted_results.sort_by(|a, b| match (a.median_latency_ms, b.median_latency_ms) {
+        (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+
+    for result in sorted_results {
+        let answered = format!("{}/{}", result.answered, result.samples);
+        match (
+            result.min_latency_ms,
+            result.median_latency_ms,
+            result.p95_latency_ms,
+            result.max_latency_ms,
+        ) {
+            (Some(min), Some(median), Some(p95), Some(max)) => println!(
+                "{:15} ({:40}) - {:>7} answered, min {:.2}ms, median {:.2}ms, p95 {:.2}ms, max {:.2}ms",
+                result.name,
+                result.address.to_string(),
+                answered,
+                min,
+                median,
+                p95,
+                max
+            ),
+            _ => println!(
+                "{:15} ({:40}) - {:>7} answered, FAILED: {}",
+                result.name,
+                result.address.to_string(),
+                answered,
+                result.last_error.as_deref().unwrap_or("no answer")
+            ),
+        }
+    }
+}
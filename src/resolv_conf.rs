@@ -1,24 +1,53 @@
 use anyhow::{Context, Result};
-use log::{info, warn};
+use log::{debug, info, warn};
+use similar::TextDiff;
 use std::fs;
-use std::io::Write;
+use std::io::{ErrorKind, Write};
 use std::net::IpAddr;
 use std::path::Path;
 
 pub struct ResolvConfManager {
     path: String,
+    /// Print the changes as a diff instead of writing them
+    dry_run: bool,
+    /// Servers always written first, whatever the selection
+    pinned: Vec<IpAddr>,
 }
 
 impl ResolvConfManager {
     pub fn new(path: String) -> Self {
-        Self { path }
+        Self { path, dry_run: false, pinned: Vec::new() }
     }
 
-    /// Update /etc/resolv.conf with the selected DNS servers
-    pub fn update_dns_servers(&self, dns_servers: &[IpAddr]) -> Result<()> {
-        if dns_servers.is_empty() {
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn with_pinned(mut self, pinned: Vec<IpAddr>) -> Self {
+        self.pinned = pinned;
+        self
+    }
+
+    /// Nameservers written for a selection: the pinned servers, then the selected ones
+    pub fn nameservers(&self, selected: &[IpAddr]) -> Vec<IpAddr> {
+        let mut nameservers = self.pinned.clone();
+        nameservers.extend(selected.iter().filter(|address| !self.pinned.contains(address)));
+        nameservers
+    }
+
+    /// Update /etc/resolv.conf with the selected DNS servers, after the pinned ones
+    pub fn update_dns_servers(&self, selected: &[IpAddr]) -> Result<()> {
+        if selected.is_empty() {
             anyhow::bail!("No DNS servers provided for update");
         }
+        let dns_servers = &self.nameservers(selected)[..];
+
+        if self.dry_run {
+            info!(event = "dry_run", path = self.path.as_str(); "Dry run: not writing {}", self.path);
+            print!("{}", self.diff(selected)?);
+            return Ok(());
+        }
 
         info!(
             "Updating {} with {} DNS servers",
@@ -36,6 +65,7 @@ impl ResolvConfManager {
         self.write_resolv_conf(&content)?;
 
         info!(
+            event = "resolv_conf_written", path = self.path.as_str(), selection:serde = dns_servers;
             "Successfully updated {} with DNS servers: {:?}",
             self.path, dns_servers
         );
@@ -43,13 +73,33 @@ impl ResolvConfManager {
         Ok(())
     }
 
+    /// Unified diff of what writing these servers would change, leaving the file alone
+    pub fn diff(&self, selected: &[IpAddr]) -> Result<String> {
+        let current = match fs::read_to_string(&self.path) {
+            Ok(current) => Some(current),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", self.path)),
+        };
+
+        let content = self.generate_resolv_conf(&self.nameservers(selected));
+        Ok(render_diff(&self.path, current.as_deref(), &content))
+    }
+
     fn backup_current(&self) -> Result<()> {
         let backup_path = format!("{}.backup", self.path);
 
         if Path::new(&self.path).exists() {
-            fs::copy(&self.path, &backup_path)
-                .with_context(|| format!("Failed to backup {} to {}", self.path, backup_path))?;
-            info!("Created backup at {}", backup_path);
+            match fs::copy(&self.path, &backup_path) {
+                Ok(_) => info!("Created backup at {}", backup_path),
+                // A sandboxed service may only write the files that already exist
+                Err(e) if e.kind() == ErrorKind::ReadOnlyFilesystem => {
+                    warn!("Cannot create {} in a read-only directory, create it once to keep a backup", backup_path)
+                }
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to backup {} to {}", self.path, backup_path))
+                }
+            }
         }
 
         Ok(())
@@ -80,6 +130,31 @@ impl ResolvConfManager {
     }
 
     fn write_resolv_conf(&self, content: &str) -> Result<()> {
+        if let Err(e) = self.replace_resolv_conf(content) {
+            // The directory may be read-only, with only resolv.conf itself writable,
+            // as in the unit `autodns systemd-unit` generates
+            debug!("Cannot replace {} atomically ({:#}), writing it in place", self.path, e);
+            return self.overwrite_resolv_conf(content);
+        }
+        Ok(())
+    }
+
+    /// Overwrite resolv.conf without replacing the file
+    fn overwrite_resolv_conf(&self, content: &str) -> Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open {} for writing", self.path))?;
+        file.write_all(content.as_bytes())
+            .with_context(|| format!("Failed to write {}", self.path))?;
+        file.sync_all()
+            .with_context(|| format!("Failed to sync {}", self.path))?;
+        Ok(())
+    }
+
+    /// Replace resolv.conf atomically by renaming a temporary file over it
+    fn replace_resolv_conf(&self, content: &str) -> Result<()> {
         // Try to write to a temporary file first
         let temp_path = format!("{}.tmp", self.path);
 
@@ -94,14 +169,21 @@ impl ResolvConfManager {
         }
 
         // Move temporary file to actual location
-        fs::rename(&temp_path, &self.path)
-            .with_context(|| format!("Failed to move temporary file to {}", self.path))?;
+        if let Err(e) = fs::rename(&temp_path, &self.path) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e).with_context(|| format!("Failed to move temporary file to {}", self.path));
+        }
 
         Ok(())
     }
 
     /// Check if we have permission to write to resolv.conf
     pub fn check_permissions(&self) -> Result<()> {
+        if self.dry_run {
+            debug!("Dry run: skipping the permission check for {}", self.path);
+            return Ok(());
+        }
+
         let path = Path::new(&self.path);
 
         // Check if path is a symlink (common with systemd-resolved)
@@ -132,12 +214,16 @@ impl ResolvConfManager {
         }
 
         // Check if we can write to the target location
-        // Try the complete write operation in a test mode
+        // Try the complete write operation in a test mode, or else writing the file in place
         match self.test_write_capability() {
             Ok(_) => {
                 info!("✓ Permission check passed for {}", self.path);
                 Ok(())
             }
+            Err(e) if path.exists() && fs::OpenOptions::new().write(true).open(path).is_ok() => {
+                info!("✓ Permission check passed for {}, it will be written in place ({:#})", self.path, e);
+                Ok(())
+            }
             Err(e) => {
                 anyhow::bail!(
                     "✗ No write permission for {}. This program needs to run with sudo/root privileges.\nError: {}",
@@ -194,7 +280,6 @@ impl ResolvConfManager {
     }
 
     /// Read current DNS servers from resolv.conf
-    #[allow(dead_code)]
     pub fn read_current_servers(&self) -> Result<Vec<IpAddr>> {
         if !Path::new(&self.path).exists() {
             warn!("{} does not exist", self.path);
@@ -216,6 +301,66 @@ impl ResolvConfManager {
 
         Ok(servers)
     }
+
+    /// Guess which tool last wrote resolv.conf from its header comments
+    pub fn detect_writer(&self) -> Option<&'static str> {
+        let content = fs::read_to_string(&self.path).ok()?;
+        detect_writer(&content)
+    }
+}
+
+/// Unified diff from the current content of the file, if it exists, to the new one
+fn render_diff(path: &str, current: Option<&str>, new: &str) -> String {
+    let old = current.unwrap_or("");
+    // The timestamp differs on every write, so it alone is not a change
+    if current.is_some() && without_timestamp(old) == without_timestamp(new) {
+        return format!("No changes to {}\n", path);
+    }
+
+    let old_header = if current.is_some() { path } else { "/dev/null" };
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(old_header, path)
+        .to_string()
+}
+
+/// Content without the `# Updated at:` line of the generated header
+fn without_timestamp(content: &str) -> Vec<&str> {
+    content
+        .lines()
+        .filter(|line| !line.starts_with("# Updated at:"))
+        .collect()
+}
+
+/// Header signatures left by the tools that commonly manage resolv.conf
+const WRITER_SIGNATURES: &[(&str, &str)] = &[
+    ("generated by autodns", "autodns"),
+    ("networkmanager", "NetworkManager"),
+    ("dhclient", "dhclient"),
+    ("dhcpcd", "dhcpcd"),
+    ("cloud-init", "cloud-init"),
+    ("manage-resolv-conf", "cloud-init"),
+    ("systemd-resolved", "systemd-resolved"),
+    ("resolvconf", "resolvconf"),
+    ("netconfig", "netconfig"),
+    ("connman", "connman"),
+];
+
+/// Guess which tool wrote the given resolv.conf content
+pub fn detect_writer(content: &str) -> Option<&'static str> {
+    // Only look at the leading comment block, where tools put their signature
+    let header = content
+        .lines()
+        .map(str::trim)
+        .take_while(|line| line.is_empty() || line.starts_with('#') || line.starts_with(';'))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .to_lowercase();
+
+    WRITER_SIGNATURES
+        .iter()
+        .find(|(signature, _)| header.contains(signature))
+        .map(|(_, writer)| *writer)
 }
 
 // Add chrono dependency for timestamps
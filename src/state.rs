@@ -0,0 +1,202 @@
+use crate::health::{HealthTracker, Sample, ServerHealth, ServerState};
+use crate::status::unix_now;
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Bump when the layout of the state file changes incompatibly
+const STATE_VERSION: u32 = 1;
+
+/// Daemon knowledge that survives restarts
+#[derive(Debug, Serialize, Deserialize)]
+struct StateFile {
+    version: u32,
+    /// Unix timestamp of the last save
+    saved_at: u64,
+    selection: Vec<IpAddr>,
+    /// Whether the selection was written by the all-offline policy
+    #[serde(default)]
+    fallback: bool,
+    servers: Vec<PersistedServer>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedServer {
+    address: IpAddr,
+    state: ServerState,
+    consecutive_failures: u32,
+    consecutive_successes: u32,
+    cooldown_seconds: u64,
+    /// Unix timestamp until which the server is cooling down
+    cooldown_until: Option<u64>,
+    ewma_ms: Option<f64>,
+    samples: Vec<Sample>,
+}
+
+/// State restored from disk at startup
+pub struct RestoredState {
+    pub selection: Vec<IpAddr>,
+    pub fallback: bool,
+    pub servers: Vec<(IpAddr, ServerHealth)>,
+}
+
+/// Reads and writes the daemon state file
+pub struct StateStore {
+    path: String,
+}
+
+impl StateStore {
+    pub fn new(path: String) -> Self {
+        Self { path }
+    }
+
+    /// Load the saved state, falling back to a fresh start if the file is
+    /// missing, unreadable, corrupt or from an incompatible version.
+    /// Restored cooldowns are capped at `max_cooldown`
+    pub fn load(&self, max_cooldown: Duration) -> Option<RestoredState> {
+        if !Path::new(&self.path).exists() {
+            info!("No saved state at {}, starting fresh", self.path);
+            return None;
+        }
+
+        match self.try_load(max_cooldown) {
+            Ok(state) => {
+                info!(
+                    "Restored health history of {} DNS servers from {}",
+                    state.servers.len(),
+                    self.path
+                );
+                Some(state)
+            }
+            Err(e) => {
+                warn!("Ignoring state file {}: {:#}. Starting fresh", self.path, e);
+                None
+            }
+        }
+    }
+
+    fn try_load(&self, max_cooldown: Duration) -> Result<RestoredState> {
+        let content = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read {}", self.path))?;
+
+        let file: StateFile = serde_json::from_str(&content).context("Failed to parse state file")?;
+
+        if file.version != STATE_VERSION {
+            bail!(
+                "unsupported state file version {} (expected {})",
+                file.version,
+                STATE_VERSION
+            );
+        }
+
+        let now_unix = unix_now();
+        let now = Instant::now();
+
+        debug!(
+            "State file was saved {} seconds ago",
+            now_unix.saturating_sub(file.saved_at)
+        );
+
+        let servers = file
+            .servers
+            .into_iter()
+            .filter_map(|server| {
+                // Translate the wall-clock cooldown back into a monotonic
+                // deadline, never further out than the configured maximum
+                let cooldown_until = match server.cooldown_until {
+                    Some(until) => {
+                        let remaining = Duration::from_secs(until.saturating_sub(now_unix));
+                        match now.checked_add(remaining.min(max_cooldown)) {
+                            Some(deadline) => Some(deadline),
+                            None => {
+                                warn!(
+                                    "Dropping saved state of {}: cooldown out of range",
+                                    server.address
+                                );
+                                return None;
+                            }
+                        }
+                    }
+                    None => None,
+                };
+
+                let health = ServerHealth {
+                    state: server.state,
+                    consecutive_failures: server.consecutive_failures,
+                    consecutive_successes: server.consecutive_successes,
+                    cooldown: Duration::from_secs(server.cooldown_seconds).min(max_cooldown),
+                    cooldown_until,
+                    ewma_ms: server.ewma_ms.filter(|ewma| ewma.is_finite() && *ewma >= 0.0),
+                    samples: server.samples.into_iter().collect(),
+                };
+                Some((server.address, health))
+            })
+            .collect();
+
+        Ok(RestoredState {
+            selection: file.selection,
+            fallback: file.fallback,
+            servers,
+        })
+    }
+
+    /// Save the current state, replacing the file atomically
+    pub fn save(&self, selection: &[IpAddr], fallback: bool, health: &HealthTracker) -> Result<()> {
+        let now_unix = unix_now();
+        let now = Instant::now();
+
+        let servers = health
+            .iter()
+            .map(|(address, health)| PersistedServer {
+                address: *address,
+                state: health.state,
+                consecutive_failures: health.consecutive_failures,
+                consecutive_successes: health.consecutive_successes,
+                cooldown_seconds: health.cooldown.as_secs(),
+                cooldown_until: health
+                    .cooldown_until
+                    .map(|until| now_unix + until.saturating_duration_since(now).as_secs()),
+                ewma_ms: health.ewma_ms,
+                samples: health.samples.iter().copied().collect(),
+            })
+            .collect();
+
+        let file = StateFile {
+            version: STATE_VERSION,
+            saved_at: now_unix,
+            selection: selection.to_vec(),
+            fallback,
+            servers,
+        };
+
+        let content = serde_json::to_string_pretty(&file).context("Failed to serialize state")?;
+
+        if let Some(parent) = Path::new(&self.path).parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+        }
+
+        let temp_path = format!("{}.tmp", self.path);
+        {
+            let mut file = fs::File::create(&temp_path)
+                .with_context(|| format!("Failed to create temporary file {}", temp_path))?;
+            file.write_all(content.as_bytes())
+                .context("Failed to write to temporary file")?;
+            file.sync_all().context("Failed to sync temporary file")?;
+        }
+
+        fs::rename(&temp_path, &self.path)
+            .with_context(|| format!("Failed to move temporary file to {}", self.path))?;
+
+        debug!("Saved state to {}", self.path);
+        Ok(())
+    }
+}
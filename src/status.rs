@@ -0,0 +1,133 @@
+use crate::health::{HealthTracker, ServerState};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of recent errors kept for the status output
+const MAX_RECENT_ERRORS: usize = 20;
+
+pub type SharedStatus = Arc<Mutex<DaemonStatus>>;
+
+/// What a running daemon reports through the control socket
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub version: String,
+    pub mode: String,
+    /// Unix timestamps, in seconds
+    pub started_at: u64,
+    pub uptime_seconds: u64,
+    pub last_round_at: Option<u64>,
+    pub next_round_at: Option<u64>,
+    pub selection: Vec<SelectedServer>,
+    pub servers: Vec<ServerStatus>,
+    pub external_changes: u64,
+    /// Why rounds are being skipped, while the host itself is offline
+    #[serde(default)]
+    pub network_unavailable: Option<String>,
+    /// Operator overrides made through the control socket
+    pub paused: bool,
+    pub pinned: Option<SelectedServer>,
+    pub excluded: Vec<ExcludedServer>,
+    pub recent_errors: VecDeque<ErrorEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelectedServer {
+    pub name: String,
+    pub address: IpAddr,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExcludedServer {
+    pub name: String,
+    pub address: IpAddr,
+    /// Unix timestamp when the exclusion ends, if it is temporary
+    pub until: Option<u64>,
+}
+
+/// Health of one configured server as of the last probe
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerStatus {
+    pub name: String,
+    pub address: IpAddr,
+    pub state: ServerState,
+    /// Outcome of the last probe, if the server was probed
+    pub last_online: Option<bool>,
+    pub last_latency_ms: Option<f64>,
+    pub smoothed_latency_ms: Option<f64>,
+    pub loss_ratio: f64,
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorEntry {
+    pub at: u64,
+    pub message: String,
+}
+
+impl DaemonStatus {
+    pub fn shared(mode: String) -> SharedStatus {
+        Arc::new(Mutex::new(Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            mode,
+            started_at: unix_now(),
+            uptime_seconds: 0,
+            last_round_at: None,
+            next_round_at: None,
+            selection: Vec::new(),
+            servers: Vec::new(),
+            external_changes: 0,
+            network_unavailable: None,
+            paused: false,
+            pinned: None,
+            excluded: Vec::new(),
+            recent_errors: VecDeque::new(),
+        }))
+    }
+
+    /// Copy of the status as of now, for answering a request
+    pub fn snapshot(&self) -> Self {
+        let mut snapshot = self.clone();
+        snapshot.uptime_seconds = unix_now().saturating_sub(self.started_at);
+        snapshot
+    }
+
+    pub fn set_servers(&mut self, servers: &[(IpAddr, String)], health: &HealthTracker) {
+        self.servers = servers
+            .iter()
+            .map(|(address, name)| {
+                let server = health.get(*address);
+                let last = server.and_then(|h| h.samples.back());
+                ServerStatus {
+                    name: name.clone(),
+                    address: *address,
+                    state: server.map(|h| h.state).unwrap_or(ServerState::Unknown),
+                    last_online: last.map(|s| s.online),
+                    last_latency_ms: last.and_then(|s| s.latency_ms),
+                    smoothed_latency_ms: server.and_then(|h| h.ewma_ms),
+                    loss_ratio: server.map(|h| h.loss_ratio()).unwrap_or(0.0),
+                    consecutive_failures: server.map(|h| h.consecutive_failures).unwrap_or(0),
+                }
+            })
+            .collect();
+    }
+
+    pub fn record_error(&mut self, message: String) {
+        self.recent_errors.push_back(ErrorEntry {
+            at: unix_now(),
+            message,
+        });
+        while self.recent_errors.len() > MAX_RECENT_ERRORS {
+            self.recent_errors.pop_front();
+        }
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
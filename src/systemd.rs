@@ -0,0 +1,228 @@
+use crate::config::Config;
+use crate::logging::LogTarget;
+use anyhow::{bail, Result};
+use log::{debug, warn};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::Path;
+use std::time::Duration;
+
+/// WatchdogSec of the unit files we ship and generate
+pub const WATCHDOG_SEC: u64 = 60;
+
+/// Directory systemd creates for the generated unit (`RuntimeDirectory=autodns`)
+pub const RUNTIME_DIR: &str = "/run/autodns";
+
+/// Environment variables systemd uses to talk to the service, not meant for hooks
+pub const NOTIFY_ENV: [&str; 3] = ["NOTIFY_SOCKET", "WATCHDOG_USEC", "WATCHDOG_PID"];
+
+/// Client of the sd_notify protocol, present when systemd started us with
+/// Type=notify (or NotifyAccess) and set NOTIFY_SOCKET
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+}
+
+impl Notifier {
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("NOTIFY_SOCKET").ok()?;
+
+        let address = match path.strip_prefix('@') {
+            // Abstract namespace socket
+            Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+            None => SocketAddr::from_pathname(&path),
+        };
+
+        let notifier = address
+            .and_then(|address| Ok(Self { socket: UnixDatagram::unbound()?, address }));
+
+        match notifier {
+            Ok(notifier) => {
+                debug!("Notifying systemd through {}", path);
+                Some(notifier)
+            }
+            Err(e) => {
+                warn!("Cannot notify systemd through {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn notify(&self, state: &str) {
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.address) {
+            debug!("Failed to notify systemd ({}): {}", state.trim_end(), e);
+        }
+    }
+
+    /// Tell systemd the service is up; units ordered after us may start
+    pub fn ready(&self, status: &str) {
+        self.notify(&format!("READY=1\nSTATUS={}\n", status));
+    }
+
+    /// Free-form status shown by `systemctl status`
+    pub fn status(&self, status: &str) {
+        self.notify(&format!("STATUS={}\n", status));
+    }
+
+    pub fn watchdog(&self) {
+        self.notify("WATCHDOG=1\n");
+    }
+}
+
+/// How often to ping the watchdog: half of WatchdogSec, if systemd enabled it for us
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+
+    // The watchdog may be meant for another process of the service
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+
+    if usec == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Render a hardened unit for this configuration: the filesystem is read-only
+/// except for the files and directories autodns actually writes to
+pub fn render_unit(config: &Config, config_path: &Path, binary: &Path, dynamic_user: bool) -> Result<String> {
+    let mut directives = Vec::new();
+    let mut read_write = Vec::new();
+
+    let runtime_dir = Path::new(RUNTIME_DIR);
+    let resolv_conf = Path::new(config.resolv_conf_path());
+    if dynamic_user {
+        // Without root or CAP_DAC_OVERRIDE, the transient user can only write
+        // a resolv.conf in a directory of its own
+        if resolv_conf.parent() != Some(runtime_dir) {
+            bail!(
+                "With --dynamic-user, resolv_conf_path must be in {} (with /etc/resolv.conf a symlink to it), not {}",
+                runtime_dir.display(),
+                resolv_conf.display()
+            );
+        }
+        directives.push("RuntimeDirectory=autodns".to_string());
+        directives.push("RuntimeDirectoryPreserve=yes".to_string());
+    } else {
+        // Only resolv.conf itself is writable, so it is written in place; its backup
+        // and temporary file are used when they already exist
+        read_write.push(resolv_conf.display().to_string());
+        read_write.push(format!("-{}.backup", resolv_conf.display()));
+        read_write.push(format!("-{}.tmp", resolv_conf.display()));
+    }
+
+    if let Some(state_file) = &config.state_file {
+        match Path::new(state_file).parent() {
+            Some(dir) if dir == Path::new("/var/lib/autodns") => {
+                directives.push("StateDirectory=autodns".to_string())
+            }
+            Some(dir) if !dir.as_os_str().is_empty() => read_write.push(dir.display().to_string()),
+            _ => {}
+        }
+    }
+
+    if let Some(socket) = &config.control_socket {
+        match Path::new(socket).parent() {
+            Some(dir) if dir == runtime_dir => directives.push("RuntimeDirectory=autodns".to_string()),
+            Some(dir) if !dir.as_os_str().is_empty() => read_write.push(dir.display().to_string()),
+            _ => {}
+        }
+    }
+
+    read_write.sort();
+    read_write.dedup();
+    directives.sort();
+    directives.dedup();
+
+    let mut capabilities = Vec::new();
+    if config.metrics.enabled && config.metrics.listen.port() < 1024 {
+        capabilities.push("CAP_NET_BIND_SERVICE");
+    }
+
+    let mut unit = String::new();
+    let mut line = |text: &str| {
+        unit.push_str(text);
+        unit.push('\n');
+    };
+
+    line("# Generated by `autodns systemd-unit` for this configuration.");
+    line("# Generate it again when the paths in the configuration change.");
+    line("[Unit]");
+    line("Description=Autodns - Automatic DNS monitoring and benchmarking");
+    line("After=network-online.target");
+    line("Wants=network-online.target");
+    line("");
+    line("[Service]");
+    line("Type=notify");
+    line("NotifyAccess=main");
+    line(&format!("WatchdogSec={}s", WATCHDOG_SEC));
+    line(&format!(
+        "ExecStart={} --config {} run",
+        binary.display(),
+        config_path.display()
+    ));
+    line("Restart=on-failure");
+    line("RestartSec=10s");
+    line("Environment=RUST_LOG=info");
+    line("SyslogIdentifier=autodns");
+    line("");
+
+    if dynamic_user {
+        line("DynamicUser=yes");
+        line(&format!("AmbientCapabilities={}", capabilities.join(" ")));
+    } else {
+        line("User=root");
+        line("Group=root");
+    }
+    line(&format!("CapabilityBoundingSet={}", capabilities.join(" ")));
+    line("");
+
+    line("# Read-only system, except where autodns writes");
+    line("ProtectSystem=strict");
+    for path in &read_write {
+        line(&format!("ReadWritePaths={}", path));
+    }
+    for directive in &directives {
+        line(directive);
+    }
+    line("");
+
+    line("NoNewPrivileges=yes");
+    line("PrivateTmp=yes");
+    line("ProtectHome=yes");
+    // /dev/log is not available with private devices
+    if config.log_target != LogTarget::Syslog {
+        line("PrivateDevices=yes");
+    }
+    line("ProtectKernelTunables=yes");
+    line("ProtectKernelModules=yes");
+    line("ProtectKernelLogs=yes");
+    line("ProtectControlGroups=yes");
+    line("ProtectClock=yes");
+    line("ProtectHostname=yes");
+    line("RestrictNamespaces=yes");
+    line("RestrictRealtime=yes");
+    line("RestrictSUIDSGID=yes");
+    line("LockPersonality=yes");
+    line("MemoryDenyWriteExecute=yes");
+    line("RestrictAddressFamilies=AF_UNIX AF_INET AF_INET6");
+    line("SystemCallArchitectures=native");
+    line("SystemCallFilter=@system-service");
+
+    let hooks = &config.hooks;
+    if [&hooks.on_change, &hooks.on_all_offline, &hooks.on_recovery, &hooks.on_write_error]
+        .iter()
+        .any(|hook| hook.is_some())
+    {
+        line("# Hooks run with the same restrictions: relax them if a hook needs more");
+    }
+
+    line("");
+    line("[Install]");
+    line("WantedBy=multi-user.target");
+
+    Ok(unit)
+}
@@ -0,0 +1,75 @@
+use anyhow::{bail, Context, Result};
+use futures::StreamExt;
+use inotify::{EventStream, Inotify, WatchMask};
+use log::debug;
+use std::ffi::OsString;
+use std::path::Path;
+use std::time::Duration;
+
+/// Events that arrive this close together are treated as a single change
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Watches the managed resolv.conf for changes made by other tools
+pub struct ResolvConfWatcher {
+    events: EventStream<[u8; 4096]>,
+    file_name: OsString,
+}
+
+impl ResolvConfWatcher {
+    pub fn new(path: &str) -> Result<Self> {
+        let path = Path::new(path);
+        let file_name = path
+            .file_name()
+            .with_context(|| format!("Cannot determine file name of {}", path.display()))?
+            .to_os_string();
+
+        // Watch the parent directory: most tools replace resolv.conf with a
+        // rename, which would silently drop a watch placed on the file itself
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        let inotify = Inotify::init().context("Failed to initialize inotify")?;
+        inotify
+            .watches()
+            .add(
+                parent,
+                WatchMask::CLOSE_WRITE
+                    | WatchMask::MOVED_TO
+                    | WatchMask::MOVED_FROM
+                    | WatchMask::CREATE
+                    | WatchMask::DELETE,
+            )
+            .with_context(|| format!("Failed to watch {}", parent.display()))?;
+
+        let events = inotify
+            .into_event_stream([0u8; 4096])
+            .context("Failed to create inotify event stream")?;
+
+        Ok(Self { events, file_name })
+    }
+
+    /// Wait until the watched file is written, replaced or removed
+    pub async fn changed(&mut self) -> Result<()> {
+        loop {
+            let event = match self.events.next().await {
+                Some(event) => event.context("Failed to read inotify event")?,
+                None => bail!("inotify event stream closed"),
+            };
+
+            if event.name.as_deref() == Some(self.file_name.as_os_str()) {
+                debug!("inotify event {:?} on {:?}", event.mask, self.file_name);
+                break;
+            }
+        }
+
+        // A single rewrite usually produces several events (create, write, move).
+        // Drain them so the caller sees one change per rewrite.
+        while let Ok(Some(event)) = tokio::time::timeout(DEBOUNCE, self.events.next()).await {
+            event.context("Failed to read inotify event")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+use crate::config::{WebhookConfig, WebhookTemplate};
+use crate::hooks::{HookEvent, HookPayload, HookServer};
+use crate::network;
+use crate::status::SharedStatus;
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Window of the rate limit
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Sends notifications to the configured webhook from a background task, one at
+/// a time and in order, so retries never delay the DNS loop
+pub struct Webhook {
+    sender: mpsc::UnboundedSender<HookPayload>,
+}
+
+impl Webhook {
+    /// Start the webhook task if the webhook is enabled
+    pub fn spawn(config: WebhookConfig, status: SharedStatus) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let Some(url) = config.url.clone() else {
+            bail!("webhook.url is required when the webhook is enabled");
+        };
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_seconds))
+            .user_agent(concat!("autodns/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .context("Failed to create webhook HTTP client")?;
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        info!("Sending {:?} webhook notifications to {}", config.template, url);
+
+        let worker = Worker {
+            config,
+            url,
+            client,
+            status,
+            host: network::hostname(),
+            sent: VecDeque::new(),
+        };
+        tokio::spawn(worker.run(receiver));
+
+        Ok(Some(Self { sender }))
+    }
+
+    /// Queue a notification for the event; write errors are left to local hooks
+    pub fn notify(&self, payload: &HookPayload) {
+        if matches!(payload.event, HookEvent::WriteError) {
+            return;
+        }
+        let _ = self.sender.send(payload.clone());
+    }
+}
+
+struct Worker {
+    config: WebhookConfig,
+    url: String,
+    client: reqwest::Client,
+    status: SharedStatus,
+    host: String,
+    /// When the notifications of the last hour were sent, oldest first
+    sent: VecDeque<Instant>,
+}
+
+impl Worker {
+    async fn run(mut self, mut receiver: mpsc::UnboundedReceiver<HookPayload>) {
+        while let Some(payload) = receiver.recv().await {
+            if !self.allow() {
+                self.report(format!(
+                    "Dropped {} webhook notification: more than {} per hour",
+                    payload.event.name(),
+                    self.config.max_per_hour
+                ));
+                continue;
+            }
+
+            let body = render(self.config.template, &payload, &self.host);
+            if let Err(e) = self.deliver(&body).await {
+                self.report(format!("{} webhook notification failed: {:#}", payload.event.name(), e));
+            }
+        }
+    }
+
+    /// Whether another notification fits in the rate limit, counting it if so
+    fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= RATE_LIMIT_WINDOW)
+        {
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() >= self.config.max_per_hour {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+
+    /// POST the body, retrying with exponential backoff on network errors,
+    /// server errors and 429
+    async fn deliver(&self, body: &Value) -> Result<()> {
+        let mut backoff = Duration::from_secs(self.config.retry_backoff_seconds);
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+            let error = match self.client.post(&self.url).json(body).send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Webhook accepted the notification ({})", response.status());
+                    return Ok(());
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    if status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+                        bail!("rejected with {}", status);
+                    }
+                    format!("answered {}", status)
+                }
+                Err(e) => e.to_string(),
+            };
+
+            if attempt > self.config.retries {
+                bail!("{} after {} attempts", error, attempt);
+            }
+
+            warn!(
+                "Webhook notification attempt {} {}, retrying in {} seconds",
+                attempt,
+                error,
+                backoff.as_secs()
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    fn report(&self, message: String) {
+        warn!(event = "webhook_failed", error = message.as_str(); "{}", message);
+        if let Ok(mut status) = self.status.lock() {
+            status.record_error(message);
+        }
+    }
+}
+
+/// Build the request body for the template
+fn render(template: WebhookTemplate, payload: &HookPayload, host: &str) -> Value {
+    let (title, text) = describe(payload, host);
+
+    match template {
+        WebhookTemplate::Generic => {
+            let mut body = serde_json::to_value(payload).unwrap_or_else(|_| json!({}));
+            body["host"] = json!(host);
+            body["message"] = json!(format!("{}: {}", title, text));
+            body
+        }
+        WebhookTemplate::Slack => json!({
+            "text": format!("*{}*\n{}", title, text),
+        }),
+        WebhookTemplate::Teams => json!({
+            "@type": "MessageCard",
+            "@context": "https://schema.org/extensions",
+            "themeColor": match payload.event {
+                HookEvent::AllOffline | HookEvent::WriteError => "D70000",
+                HookEvent::Recovery => "2EB67D",
+                HookEvent::Change => "0076D7",
+            },
+            "summary": title,
+            "title": title,
+            "text": text,
+        }),
+    }
+}
+
+/// Title and human readable text of the notification
+fn describe(payload: &HookPayload, host: &str) -> (String, String) {
+    match payload.event {
+        HookEvent::Change => (
+            format!("DNS servers changed on {}", host),
+            format!(
+                "{} → {}",
+                list_servers(&payload.old_servers),
+                list_servers(&payload.new_servers)
+            ),
+        ),
+        HookEvent::AllOffline => (
+            format!("All DNS servers are offline on {}", host),
+            // The all-offline policy may have written other servers
+            if payload.new_servers.is_empty() {
+                format!("resolv.conf still lists {}", list_servers(&payload.old_servers))
+            } else {
+                format!(
+                    "resolv.conf now lists {} instead of {}",
+                    list_servers(&payload.new_servers),
+                    list_servers(&payload.old_servers)
+                )
+            },
+        ),
+        HookEvent::Recovery => (
+            format!("DNS servers recovered on {}", host),
+            format!("Online again: {}", list_servers(&payload.new_servers)),
+        ),
+        HookEvent::WriteError => (
+            format!("Failed to update resolv.conf on {}", host),
+            payload.error.clone().unwrap_or_default(),
+        ),
+    }
+}
+
+fn list_servers(servers: &[HookServer]) -> String {
+    if servers.is_empty() {
+        return "none".to_string();
+    }
+    servers
+        .iter()
+        .map(|server| match server.latency_ms {
+            Some(latency) => format!("{} ({}, {:.2}ms)", server.name, server.address, latency),
+            None => format!("{} ({})", server.name, server.address),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -1,31 +1,69 @@
 use std::fs;
-use std::net::IpAddr;
-use std::path::PathBuf;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 use tempfile::TempDir;
 
+/// A DNS server of a test config, with optional per-server settings
+pub struct TestServer {
+    name: String,
+    address: String,
+    settings: Vec<(String, String)>,
+}
+
+impl TestServer {
+    pub fn new(name: &str, address: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            address: address.to_string(),
+            settings: Vec::new(),
+        }
+    }
+
+    /// Add a per-server setting, e.g. `.with("priority", "1")`; the value is written as YAML
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.settings.push((key.to_string(), value.to_string()));
+        self
+    }
+}
+
+impl From<(&str, &str)> for TestServer {
+    fn from((name, address): (&str, &str)) -> Self {
+        Self::new(name, address)
+    }
+}
+
 /// Helper to create a temporary config file for testing
 ///
 /// # Arguments
 /// * `temp_dir` - Temporary directory to store config and resolv.conf
-/// * `mode` - Operating mode: "firstonline" or "benchmark"
-/// * `dns_servers` - List of DNS servers as (name, address) tuples
+/// * `mode` - Operating mode: "firstonline", "benchmark" or "hybrid"
+/// * `dns_servers` - List of DNS servers as (name, address) tuples or `TestServer`s
 /// * `timeout_seconds` - DNS query timeout in seconds
 ///
 /// # Returns
 /// Path to the created config file
-pub fn create_test_config(
+pub fn create_test_config<S: Into<TestServer>>(
     temp_dir: &TempDir,
     mode: &str,
-    dns_servers: Vec<(&str, &str)>,
+    dns_servers: Vec<S>,
     timeout_seconds: u64,
 ) -> PathBuf {
     let config_path = temp_dir.path().join("config.yaml");
     let resolv_path = temp_dir.path().join("resolv.conf");
 
     let mut config_content = String::from("dns_servers:\n");
-    for (name, address) in dns_servers {
-        config_content.push_str(&format!("  - name: \"{}\"\n", name));
-        config_content.push_str(&format!("    address: \"{}\"\n", address));
+    for server in dns_servers {
+        let server = server.into();
+        config_content.push_str(&format!("  - name: \"{}\"\n", server.name));
+        config_content.push_str(&format!("    address: \"{}\"\n", server.address));
+        for (key, value) in &server.settings {
+            config_content.push_str(&format!("    {}: {}\n", key, value));
+        }
     }
 
     config_content.push_str(&format!("\nmode: {}\n", mode));
@@ -38,6 +76,17 @@ pub fn create_test_config(
     config_path
 }
 
+/// Helper to append extra settings to a config created by `create_test_config`
+///
+/// # Arguments
+/// * `config_path` - Path to the config file
+/// * `yaml` - YAML lines to append
+pub fn append_to_config(config_path: &PathBuf, yaml: &str) {
+    let mut content = fs::read_to_string(config_path).expect("Failed to read test config");
+    content.push_str(yaml);
+    fs::write(config_path, content).expect("Failed to write test config");
+}
+
 /// Helper to read resolv.conf and extract DNS IP addresses
 ///
 /// # Arguments
@@ -57,3 +106,143 @@ pub fn read_resolv_conf(path: &PathBuf) -> Vec<IpAddr> {
         })
         .collect()
 }
+
+/// Helper to run an autodns subcommand against a config file
+///
+/// # Arguments
+/// * `config_path` - Path to the config file
+/// * `args` - Subcommand and its arguments
+pub fn run_autodns(config_path: &Path, args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_autodns"))
+        .args(["--config", config_path.to_str().unwrap()])
+        .args(args)
+        .output()
+        .expect("Failed to execute autodns")
+}
+
+/// Helper to wait until a running daemon reports the status of every server
+///
+/// # Arguments
+/// * `config_path` - Path to the config file the daemon runs with
+/// * `daemon` - The daemon process, killed if it does not answer in time
+/// * `server_count` - Number of configured servers
+///
+/// # Returns
+/// The status printed by `status --json`
+pub fn wait_for_status(config_path: &Path, daemon: &mut Child, server_count: usize) -> serde_json::Value {
+    let deadline = Instant::now() + Duration::from_secs(20);
+    loop {
+        let output = run_autodns(config_path, &["status", "--json"]);
+
+        if output.status.success() {
+            let status: serde_json::Value =
+                serde_json::from_slice(&output.stdout).expect("status --json should print JSON");
+            if status["servers"].as_array().map(|s| s.len()) == Some(server_count) {
+                return status;
+            }
+        }
+
+        if Instant::now() > deadline {
+            let _ = daemon.kill();
+            panic!(
+                "Daemon did not report its status in time: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/// Helper to stand in for a webhook endpoint
+///
+/// # Arguments
+/// * `statuses` - HTTP status codes to answer with, one per request in order
+///
+/// # Returns
+/// The address to POST to and a channel receiving each request body
+pub fn spawn_http_stand_in(statuses: Vec<u16>) -> (SocketAddr, mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind HTTP stand-in");
+    let address = listener.local_addr().unwrap();
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        for status in statuses {
+            let Ok((mut stream, _)) = listener.accept() else {
+                return;
+            };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            // Headers, then a body of Content-Length bytes
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap_or(0);
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            let _ = reader.read_exact(&mut body);
+
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {} Stand-in\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            );
+            let _ = sender.send(String::from_utf8_lossy(&body).to_string());
+        }
+    });
+
+    (address, receiver)
+}
+
+/// Helper to stand in for a DNS server, answering every A query with 192.0.2.53
+///
+/// # Arguments
+/// * `address` - Loopback address to listen on, port 53
+/// * `delay` - How long to wait before each answer, to order the benchmark
+///
+/// # Returns
+/// A switch that makes the server stop answering once cleared, or None if
+/// port 53 cannot be bound (tests need root for it)
+pub fn spawn_dns_stand_in(address: &str, delay: Duration) -> Option<Arc<AtomicBool>> {
+    let socket = UdpSocket::bind((address, 53)).ok()?;
+    let answering = Arc::new(AtomicBool::new(true));
+    let switch = answering.clone();
+
+    std::thread::spawn(move || {
+        let mut buffer = [0; 512];
+        loop {
+            let Ok((length, peer)) = socket.recv_from(&mut buffer) else {
+                return;
+            };
+            if !answering.load(Ordering::SeqCst) || length < 17 {
+                continue;
+            }
+            std::thread::sleep(delay);
+
+            // Header and question of the query, up to the end of QNAME plus QTYPE and QCLASS
+            let query = &buffer[..length];
+            let Some(name_end) = query[12..].iter().position(|&byte| byte == 0) else {
+                continue;
+            };
+            let question = &query[12..12 + name_end + 5];
+            let is_a = question[question.len() - 4..question.len() - 2] == [0, 1];
+
+            let mut response = query[..2].to_vec();
+            response.extend_from_slice(&[0x81, 0x80, 0, 1, 0, is_a as u8, 0, 0, 0, 0]);
+            response.extend_from_slice(question);
+            if is_a {
+                // Pointer to the question name, type A, class IN, TTL 60, 4 bytes
+                response.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 53]);
+            }
+            let _ = socket.send_to(&response, peer);
+        }
+    });
+
+    Some(switch)
+}
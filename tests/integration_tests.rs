@@ -1,7 +1,16 @@
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 mod helpers;
 
-use helpers::{create_test_config, read_resolv_conf};
+use helpers::{
+    append_to_config, create_test_config, read_resolv_conf, run_autodns, spawn_dns_stand_in,
+    spawn_http_stand_in, wait_for_status, TestServer,
+};
+use std::io::{BufRead, BufReader, Write};
 use std::net::IpAddr;
+use std::process::{Command, Stdio};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 use tempfile::TempDir;
 
 #[tokio::test]
@@ -247,3 +256,1298 @@ async fn test_timeout_configuration() {
 
     println!("✓ Test passed: Timeout configuration respected (completed in {:?})", elapsed);
 }
+
+#[tokio::test]
+async fn test_status_reports_running_daemon() {
+    // Test: `status` should report the state of a running daemon over the control socket
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    let socket_path = temp_dir.path().join("autodns.sock");
+    // A single failed probe marks a server down, so the initial round settles the state
+    append_to_config(
+        &config_path,
+        &format!(
+            "state_file: null\ncontrol_socket: \"{}\"\nhealth:\n  failure_threshold: 1\n",
+            socket_path.display()
+        ),
+    );
+
+    // Run the binary directly so that killing it does not leave the daemon behind
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_autodns"))
+        .args(["--config", config_path.to_str().unwrap(), "run"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start autodns daemon");
+
+    // Wait until the initial round has been reported
+    let status = wait_for_status(&config_path, &mut daemon, 2);
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+
+    assert_eq!(status["mode"], "firstonline");
+    assert!(
+        status["selection"].as_array().unwrap().is_empty(),
+        "No server should be selected when all are offline"
+    );
+    for server in status["servers"].as_array().unwrap() {
+        assert_eq!(server["state"], "down", "Unreachable server should be down");
+    }
+
+    println!("✓ Test passed: status reported the running daemon");
+}
+
+#[test]
+fn test_health_check_fails_over_before_next_round() {
+    // Test: a selected server going down should be replaced by the health check, long before the next round
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let stand_ins = [
+        spawn_dns_stand_in("127.0.27.1", Duration::ZERO),
+        spawn_dns_stand_in("127.0.27.2", Duration::from_millis(20)),
+        spawn_dns_stand_in("127.0.27.3", Duration::from_millis(60)),
+    ];
+    let [Some(fast), Some(_), Some(_)] = stand_ins else {
+        println!("Skipping: DNS stand-ins need root to bind port 53");
+        return;
+    };
+
+    let dns_servers = vec![
+        ("Fast", "127.0.27.1"),
+        ("Medium", "127.0.27.2"),
+        ("Slow", "127.0.27.3"),
+        ("Invalid", "192.0.2.1"),
+    ];
+    let config_path = create_test_config(&temp_dir, "benchmark", dns_servers, 1);
+    let config = std::fs::read_to_string(&config_path)
+        .unwrap()
+        .replace("execution_interval_seconds: 120", "execution_interval_seconds: 600");
+    std::fs::write(&config_path, config).unwrap();
+    append_to_config(
+        &config_path,
+        "state_file: null\ncontrol_socket: null\nhealth_check_interval_seconds: 1\nhealth:\n  failure_threshold: 1\n",
+    );
+    let resolv_path = temp_dir.path().join("resolv.conf");
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_autodns"))
+        .args(["--config", config_path.to_str().unwrap(), "run"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start autodns daemon");
+
+    let fast_first: Vec<IpAddr> = vec!["127.0.27.1".parse().unwrap(), "127.0.27.2".parse().unwrap()];
+    let failed_over: Vec<IpAddr> = vec!["127.0.27.2".parse().unwrap(), "127.0.27.3".parse().unwrap()];
+
+    let mut wait_for = |expected: &[IpAddr]| {
+        let deadline = Instant::now() + Duration::from_secs(15);
+        while Instant::now() < deadline {
+            if resolv_path.exists() && read_resolv_conf(&resolv_path) == expected {
+                return true;
+            }
+            if let Ok(Some(_)) = daemon.try_wait() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        false
+    };
+
+    let selected = wait_for(&fast_first);
+    fast.store(false, Ordering::SeqCst);
+    // The next round is 10 minutes away, only the health check can replace it in time
+    let replaced = selected && wait_for(&failed_over);
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+
+    assert!(selected, "The initial round should select the two fastest servers");
+    assert!(replaced, "The failed server should be replaced by the next best one");
+
+    println!("✓ Test passed: the health check failed over before the next round");
+}
+
+#[test]
+fn test_external_change_is_reverted_or_left_alone() {
+    // Test: an external rewrite of resolv.conf should be reverted by `reapply` and left alone by `backoff`
+    for (policy, reverted) in [("reapply", true), ("backoff", false)] {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+        // The fallback server gives the daemon a selection to defend without network access
+        let dns_servers = vec![
+            ("Invalid-1", "192.0.2.1"),
+            ("Invalid-2", "192.0.2.2"),
+        ];
+        let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+        let socket_path = temp_dir.path().join("autodns.sock");
+        append_to_config(
+            &config_path,
+            &format!(
+                "state_file: null\ncontrol_socket: \"{}\"\non_all_offline: fallback\n\
+                fallback_servers: [\"198.51.100.1\"]\nwatch:\n  policy: {}\n",
+                socket_path.display(),
+                policy
+            ),
+        );
+        let resolv_path = temp_dir.path().join("resolv.conf");
+        let fallback: Vec<IpAddr> = vec!["198.51.100.1".parse().unwrap()];
+        let external: Vec<IpAddr> = vec!["203.0.113.53".parse().unwrap()];
+
+        let mut daemon = Command::new(env!("CARGO_BIN_EXE_autodns"))
+            .args(["--config", config_path.to_str().unwrap(), "run"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start autodns daemon");
+
+        wait_for_status(&config_path, &mut daemon, 2);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !(resolv_path.exists() && read_resolv_conf(&resolv_path) == fallback) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        std::fs::write(&resolv_path, "nameserver 203.0.113.53\n").expect("Failed to rewrite resolv.conf");
+
+        // Wait for the daemon to notice, then give a revert time to land
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut status = wait_for_status(&config_path, &mut daemon, 2);
+        while status["external_changes"] == 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(100));
+            status = wait_for_status(&config_path, &mut daemon, 2);
+        }
+        std::thread::sleep(Duration::from_millis(500));
+        let nameservers = read_resolv_conf(&resolv_path);
+
+        let _ = daemon.kill();
+        let _ = daemon.wait();
+
+        assert_eq!(status["external_changes"], 1, "The external change should be detected ({})", policy);
+        if reverted {
+            assert_eq!(nameservers, fallback, "reapply should write the selection back");
+        } else {
+            assert_eq!(nameservers, external, "backoff should leave the change alone for now");
+        }
+    }
+
+    println!("✓ Test passed: external changes were reverted or left alone as configured");
+}
+
+#[test]
+fn test_daemon_notifies_systemd() {
+    // Test: under Type=notify the daemon should report readiness and status, then ping the watchdog
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    append_to_config(&config_path, "state_file: null\ncontrol_socket: null\n");
+
+    let notify_path = temp_dir.path().join("notify.sock");
+    let notify_socket = std::os::unix::net::UnixDatagram::bind(&notify_path).expect("Failed to bind notify socket");
+    notify_socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_autodns"))
+        .args(["--config", config_path.to_str().unwrap(), "run"])
+        .env("NOTIFY_SOCKET", &notify_path)
+        // Pings every half second
+        .env("WATCHDOG_USEC", "1000000")
+        .env_remove("WATCHDOG_PID")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start autodns daemon");
+
+    let mut messages: Vec<String> = Vec::new();
+    let pings = |messages: &[String]| messages.iter().filter(|message| message.contains("WATCHDOG=1")).count();
+    let mut buffer = [0; 1024];
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline && pings(&messages) < 2 {
+        if let Ok(length) = notify_socket.recv(&mut buffer) {
+            messages.push(String::from_utf8_lossy(&buffer[..length]).to_string());
+        }
+    }
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+
+    let ready = messages
+        .iter()
+        .find(|message| message.contains("READY=1"))
+        .expect("The daemon should notify readiness");
+    assert!(ready.contains("STATUS="), "Readiness should come with a status: {:?}", ready);
+    assert!(
+        pings(&messages) >= 2,
+        "The daemon should keep pinging the watchdog: {:?}",
+        messages
+    );
+
+    println!("✓ Test passed: the daemon notified systemd");
+}
+
+#[tokio::test]
+async fn test_restore_clamps_oversized_cooldowns() {
+    // Test: a state file with out-of-range cooldowns must not crash the daemon at startup
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    let socket_path = temp_dir.path().join("autodns.sock");
+    let state_path = temp_dir.path().join("state.json");
+    std::fs::write(
+        &state_path,
+        r#"{"version":1,"saved_at":0,"selection":[],"servers":[{"address":"192.0.2.1","state":"down",
+"consecutive_failures":3,"consecutive_successes":0,"cooldown_seconds":18446744073709551615,
+"cooldown_until":18446744073709551615,"ewma_ms":null,"samples":[]}]}"#,
+    )
+    .expect("Failed to write state file");
+    append_to_config(
+        &config_path,
+        &format!(
+            "state_file: \"{}\"\ncontrol_socket: \"{}\"\n",
+            state_path.display(),
+            socket_path.display()
+        ),
+    );
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_autodns"))
+        .args(["--config", config_path.to_str().unwrap(), "run"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start autodns daemon");
+
+    // The daemon panicked on the restored deadline before the fix
+    let status = wait_for_status(&config_path, &mut daemon, 2);
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+
+    assert_eq!(status["mode"], "firstonline");
+
+    println!("✓ Test passed: oversized cooldowns were clamped on restore");
+}
+
+#[tokio::test]
+async fn test_restore_drops_unconfigured_servers() {
+    // Test: a saved selection naming a server no longer in the configuration must not be restored
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    let socket_path = temp_dir.path().join("autodns.sock");
+    let state_path = temp_dir.path().join("state.json");
+    std::fs::write(
+        &state_path,
+        r#"{"version":1,"saved_at":0,"selection":["203.0.113.9","192.0.2.1"],"servers":[]}"#,
+    )
+    .expect("Failed to write state file");
+    append_to_config(
+        &config_path,
+        &format!(
+            "state_file: \"{}\"\ncontrol_socket: \"{}\"\n",
+            state_path.display(),
+            socket_path.display()
+        ),
+    );
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_autodns"))
+        .args(["--config", config_path.to_str().unwrap(), "run"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start autodns daemon");
+
+    let status = wait_for_status(&config_path, &mut daemon, 2);
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+
+    assert_eq!(status["mode"], "firstonline");
+
+    // No server is online, so the daemon keeps and saves the restored selection
+    let state: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&state_path).expect("Failed to read state file"))
+            .expect("State file should be valid JSON");
+    assert_eq!(
+        state["selection"],
+        serde_json::json!(["192.0.2.1"]),
+        "Only the configured server should be restored"
+    );
+
+    println!("✓ Test passed: unconfigured servers were dropped on restore");
+}
+
+#[test]
+fn test_second_daemon_refuses_to_start() {
+    // Test: a daemon should replace a stale control socket, but not the socket of a running daemon
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    let socket_path = temp_dir.path().join("autodns.sock");
+    append_to_config(
+        &config_path,
+        &format!("state_file: null\ncontrol_socket: \"{}\"\n", socket_path.display()),
+    );
+
+    // Left behind by a daemon that was killed
+    drop(std::os::unix::net::UnixListener::bind(&socket_path).expect("Failed to create stale socket"));
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_autodns"))
+        .args(["--config", config_path.to_str().unwrap(), "run"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start autodns daemon");
+    wait_for_status(&config_path, &mut daemon, 2);
+
+    let mut second = Command::new(env!("CARGO_BIN_EXE_autodns"))
+        .args(["--config", config_path.to_str().unwrap(), "run"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start second autodns daemon");
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while second.try_wait().unwrap().is_none() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    let _ = second.kill();
+    let second = second.wait_with_output().unwrap();
+    let still_running = run_autodns(&config_path, &["status"]).status.success();
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+
+    assert_eq!(second.status.code(), Some(1), "A second daemon should refuse to start");
+    assert!(String::from_utf8_lossy(&second.stderr).contains("already running"));
+    assert!(still_running, "The first daemon should keep its control socket");
+
+    println!("✓ Test passed: a second daemon refused to start");
+}
+
+#[test]
+fn test_trigger_reports_skipped_round() {
+    // Test: a triggered round skipped by the connectivity precheck should say so
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    let socket_path = temp_dir.path().join("autodns.sock");
+    append_to_config(
+        &config_path,
+        &format!(
+            "state_file: null\ncontrol_socket: \"{}\"\n\
+            connectivity:\n  targets: [\"192.0.2.77:443\"]\n  timeout_seconds: 1\n",
+            socket_path.display()
+        ),
+    );
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_autodns"))
+        .args(["--config", config_path.to_str().unwrap(), "run"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start autodns daemon");
+
+    // No server is probed while the network is unavailable, so wait for the skipped initial round
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        let output = run_autodns(&config_path, &["status", "--json"]);
+        if output.status.success()
+            && serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()["network_unavailable"]
+                .is_string()
+        {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+
+    let output = run_autodns(&config_path, &["trigger"]);
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "trigger should fail when the round is skipped");
+    assert!(stderr.contains("Skipped the round, the network is unavailable"), "{}", stderr);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Ran a round"));
+
+    println!("✓ Test passed: trigger reported the skipped round");
+}
+
+#[tokio::test]
+async fn test_control_actions_change_daemon_state() {
+    // Test: pause and exclude should be applied by the running daemon and show up in its status
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    let socket_path = temp_dir.path().join("autodns.sock");
+    append_to_config(
+        &config_path,
+        &format!("state_file: null\ncontrol_socket: \"{}\"\n", socket_path.display()),
+    );
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_autodns"))
+        .args(["--config", config_path.to_str().unwrap(), "run"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start autodns daemon");
+
+    wait_for_status(&config_path, &mut daemon, 2);
+
+    let pause = run_autodns(&config_path, &["pause"]);
+    let exclude = run_autodns(&config_path, &["exclude", "Invalid-1", "--for", "600"]);
+    let unknown = run_autodns(&config_path, &["exclude", "Missing"]);
+    let unpin = run_autodns(&config_path, &["unpin"]);
+    let status = wait_for_status(&config_path, &mut daemon, 2);
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+
+    assert!(pause.status.success(), "pause should succeed");
+    assert!(exclude.status.success(), "exclude of a configured server should succeed");
+    assert!(!unknown.status.success(), "exclude of an unknown server should fail");
+    assert!(!unpin.status.success(), "unpin without a pinned server should fail");
+
+    assert_eq!(status["paused"], true);
+    let excluded = status["excluded"].as_array().unwrap();
+    assert_eq!(excluded.len(), 1, "Only the configured server should be excluded");
+    assert_eq!(excluded[0]["name"], "Invalid-1");
+    assert!(excluded[0]["until"].is_u64(), "A timed exclusion should report when it ends");
+
+    println!("✓ Test passed: control actions changed the daemon state");
+}
+
+#[tokio::test]
+async fn test_exclude_rejects_huge_duration() {
+    // Test: an exclusion duration that cannot be added to the clock must be refused, not crash the daemon
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    let socket_path = temp_dir.path().join("autodns.sock");
+    append_to_config(
+        &config_path,
+        &format!("state_file: null\ncontrol_socket: \"{}\"\n", socket_path.display()),
+    );
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_autodns"))
+        .args(["--config", config_path.to_str().unwrap(), "run"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start autodns daemon");
+
+    wait_for_status(&config_path, &mut daemon, 2);
+
+    // The CLI refuses it before asking the daemon
+    let cli = run_autodns(&config_path, &["exclude", "Invalid-1", "--for", "18446744073709551615"]);
+
+    // Other clients of the socket get an error reply
+    let mut stream = std::os::unix::net::UnixStream::connect(&socket_path).expect("Failed to connect");
+    stream
+        .write_all(b"{\"command\":\"exclude\",\"server\":\"Invalid-1\",\"duration_seconds\":18446744073709551615}\n")
+        .expect("Failed to send request");
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply).expect("Failed to read reply");
+
+    let status = wait_for_status(&config_path, &mut daemon, 2);
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+
+    assert!(!cli.status.success(), "exclude --for should refuse a huge duration");
+    let reply: serde_json::Value = serde_json::from_str(&reply).expect("Reply should be JSON");
+    assert_eq!(reply["result"], "error", "Unexpected reply: {}", reply);
+    assert!(status["excluded"].as_array().unwrap().is_empty(), "Nothing should be excluded");
+
+    println!("✓ Test passed: huge exclusion duration refused");
+}
+
+#[tokio::test]
+async fn test_all_offline_hook_receives_event_details() {
+    // Test: the on_all_offline hook should run with the event in its environment and as JSON on stdin
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    let env_path = temp_dir.path().join("hook.env");
+    let json_path = temp_dir.path().join("hook.json");
+    append_to_config(
+        &config_path,
+        &format!(
+            "state_file: null\ncontrol_socket: null\nhooks:\n  on_all_offline: 'echo $AUTODNS_EVENT > {}; cat > {}'\n",
+            env_path.display(),
+            json_path.display()
+        ),
+    );
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_autodns"))
+        .args(["--config", config_path.to_str().unwrap(), "run"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start autodns daemon");
+
+    // Wait for the hook to finish writing its input
+    let deadline = Instant::now() + Duration::from_secs(20);
+    let payload = loop {
+        let parsed = std::fs::read_to_string(&json_path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok());
+        if let Some(payload) = parsed {
+            break payload;
+        }
+        if Instant::now() > deadline {
+            let _ = daemon.kill();
+            panic!("on_all_offline hook did not run in time");
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    };
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+
+    let event = std::fs::read_to_string(&env_path).expect("Hook should have written its environment");
+    assert_eq!(event.trim(), "all_offline");
+    assert_eq!(payload["event"], "all_offline");
+    assert!(
+        payload["new_servers"].as_array().unwrap().is_empty(),
+        "No server should be available when all are offline"
+    );
+
+    println!("✓ Test passed: on_all_offline hook received the event details");
+}
+
+#[tokio::test]
+async fn test_webhook_retries_failed_notification() {
+    // Test: the webhook should retry a notification the endpoint failed to accept
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+
+    // The first attempt fails, the retry succeeds
+    let (address, requests) = spawn_http_stand_in(vec![500, 200]);
+
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    append_to_config(
+        &config_path,
+        &format!(
+            "state_file: null\ncontrol_socket: null\nwebhook:\n  enabled: true\n  url: \"http://{}/notify\"\n  retry_backoff_seconds: 1\n",
+            address
+        ),
+    );
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_autodns"))
+        .args(["--config", config_path.to_str().unwrap(), "run"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start autodns daemon");
+
+    let first = requests.recv_timeout(Duration::from_secs(20));
+    let retry = requests.recv_timeout(Duration::from_secs(10));
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+
+    let first = first.expect("Webhook should have been notified");
+    let retry = retry.expect("Webhook should have retried the failed notification");
+    assert_eq!(first, retry, "The retry should send the same notification");
+
+    let payload: serde_json::Value = serde_json::from_str(&retry).expect("Webhook body should be JSON");
+    assert_eq!(payload["event"], "all_offline");
+    assert!(
+        payload["message"].as_str().unwrap().contains("All DNS servers are offline"),
+        "Generic template should include a readable message"
+    );
+
+    println!("✓ Test passed: webhook retried the failed notification");
+}
+
+#[tokio::test]
+async fn test_all_offline_notification_describes_fallback_servers() {
+    // Test: the all_offline notification should tell which servers the all-offline policy wrote
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+
+    // Writing the fallback servers is a change, notified as well
+    let (address, requests) = spawn_http_stand_in(vec![200, 200]);
+
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    append_to_config(
+        &config_path,
+        &format!(
+            "state_file: null\ncontrol_socket: null\non_all_offline: fallback\nfallback_servers: [\"198.51.100.1\"]\nwebhook:\n  enabled: true\n  url: \"http://{}/notify\"\n",
+            address
+        ),
+    );
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_autodns"))
+        .args(["--config", config_path.to_str().unwrap(), "run"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start autodns daemon");
+
+    let notifications: Vec<_> = (0..2)
+        .map_while(|_| requests.recv_timeout(Duration::from_secs(20)).ok())
+        .collect();
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+
+    let payload = notifications
+        .iter()
+        .map(|body| serde_json::from_str::<serde_json::Value>(body).expect("Webhook body should be JSON"))
+        .find(|payload| payload["event"] == "all_offline")
+        .expect("Webhook should have been notified that all servers are offline");
+
+    assert_eq!(payload["new_servers"][0]["address"], "198.51.100.1");
+    let message = payload["message"].as_str().unwrap();
+    assert!(
+        message.contains("now lists") && message.contains("198.51.100.1"),
+        "Message should name the fallback servers, got: {}",
+        message
+    );
+
+    println!("✓ Test passed: all_offline notification described the fallback servers");
+}
+
+#[tokio::test]
+async fn test_json_log_format_emits_structured_events() {
+    // Test: --log-format json should print one JSON object per log line with the event fields
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    let output = run_autodns(&config_path, &["--log-format", "json", "check"]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let entries: Vec<serde_json::Value> = stderr
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|_| panic!("Log line is not JSON: {}", line)))
+        .collect();
+
+    let offline: Vec<_> = entries
+        .iter()
+        .filter(|entry| entry["event"] == "server_offline")
+        .collect();
+    assert_eq!(offline.len(), 2, "Every unreachable server should be logged as offline");
+    for entry in offline {
+        assert_eq!(entry["level"], "warn");
+        assert!(entry["server"].as_str().unwrap().starts_with("Invalid-"));
+        assert!(entry["address"].as_str().unwrap().starts_with("192.0.2."));
+        assert!(entry["error"].is_string(), "Offline servers should carry the error");
+    }
+
+    println!("✓ Test passed: JSON log format emitted structured events");
+}
+
+#[test]
+fn test_systemd_unit_only_opens_configured_paths() {
+    // Test: systemd-unit should make the system read-only except for the files and directories autodns writes to
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+
+    let output = run_autodns(&config_path, &["systemd-unit"]);
+    assert!(output.status.success(), "systemd-unit should succeed");
+
+    let unit = String::from_utf8_lossy(&output.stdout);
+    let read_write: Vec<_> = unit.lines().filter(|line| line.starts_with("ReadWritePaths=")).collect();
+
+    assert!(unit.contains("ProtectSystem=strict"));
+    let resolv_conf = temp_dir.path().join("resolv.conf");
+    assert_eq!(
+        read_write,
+        vec![
+            format!("ReadWritePaths=-{}.backup", resolv_conf.display()),
+            format!("ReadWritePaths=-{}.tmp", resolv_conf.display()),
+            format!("ReadWritePaths={}", resolv_conf.display()),
+        ],
+        "Only resolv.conf and its backup and temporary file should be writable"
+    );
+    assert!(!read_write.contains(&format!("ReadWritePaths={}", temp_dir.path().display()).as_str()));
+    // Default state file and control socket
+    assert!(unit.contains("StateDirectory=autodns"));
+    assert!(unit.contains("RuntimeDirectory=autodns"));
+    assert!(unit.lines().any(|line| line == "CapabilityBoundingSet="));
+    assert!(unit.contains(&format!("--config {} run", std::fs::canonicalize(&config_path).unwrap().display())));
+    assert!(!unit.contains("DynamicUser"));
+
+    // The transient user cannot write resolv.conf outside of its runtime directory
+    let output = run_autodns(&config_path, &["systemd-unit", "--dynamic-user"]);
+    assert!(!output.status.success(), "--dynamic-user should need resolv.conf in /run/autodns");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("/run/autodns"));
+
+    let config = std::fs::read_to_string(&config_path).unwrap().replace(
+        &resolv_conf.display().to_string(),
+        "/run/autodns/resolv.conf",
+    );
+    std::fs::write(&config_path, config).unwrap();
+
+    let output = run_autodns(&config_path, &["systemd-unit", "--dynamic-user"]);
+    assert!(output.status.success(), "systemd-unit --dynamic-user should succeed: {}", String::from_utf8_lossy(&output.stderr));
+    let unit = String::from_utf8_lossy(&output.stdout);
+    assert!(unit.contains("DynamicUser=yes"));
+    assert!(unit.contains("RuntimeDirectoryPreserve=yes"));
+    assert!(!unit.contains("CAP_DAC_OVERRIDE"));
+    assert!(!unit.contains("ReadWritePaths=/etc"));
+    assert_eq!(unit.matches("RuntimeDirectory=autodns").count(), 1);
+
+    println!("✓ Test passed: systemd unit opened only the configured paths");
+}
+
+#[test]
+fn test_validate_reports_every_problem() {
+    // Test: validate should list all errors and warnings at once and fail on errors
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    // Both servers are Cloudflare, which is only a warning
+    let dns_servers = vec![
+        ("Cloudflare-1", "1.1.1.1"),
+        ("Cloudflare-2", "1.0.0.1"),
+    ];
+
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 0);
+    append_to_config(&config_path, "health:\n  cooldown_seconds: 0\n");
+
+    let output = run_autodns(&config_path, &["validate", "--json"]);
+    assert_eq!(output.status.code(), Some(1), "validate should fail on errors");
+
+    let report: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("validate --json should print JSON");
+    assert_eq!(report["valid"], false);
+    assert_eq!(report["errors"], 2);
+    assert_eq!(report["warnings"], 1);
+
+    let diagnostics = report["diagnostics"].as_array().unwrap();
+    let fields: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d["severity"] == "error")
+        .map(|d| d["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, vec!["timeout_seconds", "health.cooldown_seconds"]);
+    assert!(diagnostics
+        .iter()
+        .any(|d| d["severity"] == "warning" && d["message"].as_str().unwrap().contains("Cloudflare")));
+
+    // Other commands refuse the configuration and name every error
+    let output = run_autodns(&config_path, &["check"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("timeout_seconds must be greater than 0"));
+    assert!(stderr.contains("health.cooldown_seconds must be greater than 0"));
+
+    println!("✓ Test passed: validate reported every problem");
+}
+
+#[test]
+fn test_environment_checked_only_before_writing() {
+    // Test: the host checks should run for validate and check, but not for client commands
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    let missing = temp_dir.path().join("missing");
+    let config = std::fs::read_to_string(&config_path).unwrap().replace(
+        &temp_dir.path().join("resolv.conf").display().to_string(),
+        &missing.join("resolv.conf").display().to_string(),
+    );
+    std::fs::write(&config_path, config).unwrap();
+    append_to_config(
+        &config_path,
+        &format!("state_file: null\ncontrol_socket: \"{}\"\n", temp_dir.path().join("autodns.sock").display()),
+    );
+    let problem = "Parent directory does not exist for resolv_conf_path";
+
+    let output = run_autodns(&config_path, &["validate"]);
+    assert_eq!(output.status.code(), Some(1), "validate should fail on the missing directory");
+    assert!(String::from_utf8_lossy(&output.stdout).contains(problem));
+
+    let output = run_autodns(&config_path, &["check"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(problem));
+
+    // No daemon is running, which is the only thing status should complain about
+    let output = run_autodns(&config_path, &["status"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains(problem), "status should not check the host: {}", stderr);
+
+    println!("✓ Test passed: the host was only checked before writing");
+}
+
+#[test]
+fn test_dry_run_prints_diff_without_writing() {
+    // Test: --dry-run should show the resolv.conf changes as a diff and leave the file alone
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Cloudflare-1", "1.1.1.1"),
+        ("Google-1", "8.8.8.8"),
+    ];
+
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 2);
+    let resolv_path = temp_dir.path().join("resolv.conf");
+    let original = "# Written by hand\nnameserver 192.0.2.53\n";
+    std::fs::write(&resolv_path, original).expect("Failed to write resolv.conf");
+
+    let output = run_autodns(&config_path, &["--dry-run", "check"]);
+    assert!(
+        output.status.success(),
+        "autodns check --dry-run failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("--- {}", resolv_path.display())));
+    assert!(stdout.contains("-nameserver 192.0.2.53"));
+    assert!(stdout.contains("+nameserver 1.1.1.1"));
+    assert!(stdout.contains("+nameserver 8.8.8.8"));
+
+    assert_eq!(std::fs::read_to_string(&resolv_path).unwrap(), original, "resolv.conf should be untouched");
+    assert!(!temp_dir.path().join("resolv.conf.backup").exists(), "No backup should be made");
+
+    println!("✓ Test passed: dry run printed the diff without writing");
+}
+
+#[test]
+fn test_dry_run_reports_unchanged_resolv_conf() {
+    // Test: a dry run repeating the last write should not report the new timestamp as a change
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    // TEST-NET addresses never answer, so the fallback servers are written every time
+    let dns_servers = vec![
+        ("Unreachable-1", "192.0.2.1"),
+        ("Unreachable-2", "192.0.2.2"),
+    ];
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    append_to_config(&config_path, "on_all_offline: fallback\nfallback_servers: [\"198.51.100.1\"]\n");
+
+    let output = run_autodns(&config_path, &["check"]);
+    assert!(output.status.success(), "autodns check command failed");
+
+    // Let the timestamp in the header move on
+    std::thread::sleep(Duration::from_millis(1100));
+
+    let output = run_autodns(&config_path, &["--dry-run", "check"]);
+    assert!(output.status.success(), "autodns check --dry-run failed");
+
+    let resolv_path = temp_dir.path().join("resolv.conf");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("No changes to {}", resolv_path.display())),
+        "Expected no changes, got: {}",
+        stdout
+    );
+
+    println!("✓ Test passed: dry run ignored the timestamp");
+}
+
+#[test]
+fn test_dry_run_rejected_by_control_commands() {
+    // Test: --dry-run must not be silently ignored by commands that make the daemon write
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![("Invalid-1", "192.0.2.1")];
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+
+    for command in ["trigger", "resume"] {
+        let output = run_autodns(&config_path, &["--dry-run", command]);
+        assert!(!output.status.success(), "{} --dry-run should fail", command);
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("--dry-run only applies to"),
+            "{} --dry-run should explain why it failed",
+            command
+        );
+    }
+
+    println!("✓ Test passed: control commands rejected --dry-run");
+}
+
+#[test]
+fn test_check_structured_output() {
+    // Test: --output json/csv should print every result, the summary and the selection
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Invalid-1", "192.0.2.1"),
+        ("Invalid-2", "192.0.2.2"),
+    ];
+
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+
+    let output = run_autodns(&config_path, &["check", "--output", "json"]);
+    assert!(output.status.success(), "check --output json should succeed");
+
+    let report: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("stdout should only hold the JSON report");
+    assert_eq!(report["command"], "check");
+    assert_eq!(report["summary"]["total"], 2);
+    assert_eq!(report["summary"]["offline"], 2);
+    assert_eq!(report["selection"], serde_json::json!([]));
+    assert_eq!(report["resolv_conf"]["written"], false);
+
+    let results = report["results"].as_array().unwrap();
+    assert_eq!(results[0]["name"], "Invalid-1");
+    assert_eq!(results[0]["address"], "192.0.2.1");
+    assert_eq!(results[0]["is_online"], false);
+    assert!(results[0]["error"].is_string(), "Offline servers should carry the error");
+
+    let output = run_autodns(&config_path, &["check", "--output", "csv"]);
+    let csv = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines[0], "host,timestamp,command,name,address,is_online,latency_ms,error,selected");
+    assert_eq!(lines.len(), 3, "One row per server");
+    assert!(lines[1].contains(",check,Invalid-1,192.0.2.1,false,"));
+
+    println!("✓ Test passed: check printed structured output");
+}
+
+#[test]
+fn test_probe_needs_no_valid_configuration() {
+    // Test: probe should measure servers without validating the configuration or touching resolv.conf
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    // A single server fails validation, but is enough to probe
+    let config_path = create_test_config(&temp_dir, "firstonline", vec![("Invalid-1", "192.0.2.1")], 1);
+
+    let output = run_autodns(&config_path, &["probe", "--samples", "2", "--output", "json"]);
+    assert!(
+        output.status.success(),
+        "probe should not validate the configuration: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).expect("probe should print JSON");
+    let results = report["results"].as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["name"], "Invalid-1");
+    assert_eq!(results[0]["samples"], 2);
+    assert_eq!(results[0]["answered"], 0);
+    assert_eq!(results[0]["loss_ratio"], 1.0);
+    assert!(results[0]["last_error"].is_string());
+
+    // Servers given on the command line do not need a configuration at all
+    let missing_config = temp_dir.path().join("missing.yaml");
+    let output = run_autodns(
+        &missing_config,
+        &["probe", "192.0.2.2", "192.0.2.3", "--samples", "1", "--timeout", "1", "--output", "csv"],
+    );
+    assert!(output.status.success(), "probe should not need a configuration file");
+    let csv = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(csv.lines().count(), 3, "Header and one row per server");
+    assert!(csv.contains(",192.0.2.2,192.0.2.2,1,0,1.0,"));
+
+    assert!(!temp_dir.path().join("resolv.conf").exists(), "probe should never write resolv.conf");
+
+    println!("✓ Test passed: probe measured servers without a valid configuration");
+}
+
+#[test]
+fn test_diverse_policy_spreads_selection_across_providers() {
+    // Test: selection_policy diverse should pick servers from different providers first
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    // Providers are recognized for well-known resolvers, and can be given explicitly
+    let dns_servers = vec![
+        TestServer::new("Cloudflare-1", "1.1.1.1"),
+        TestServer::new("Cloudflare-2", "1.0.0.1"),
+        TestServer::new("Google-1", "8.8.8.8").with("provider", "google"),
+    ];
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 2);
+    append_to_config(&config_path, "selection_policy: diverse\n");
+    let resolv_path = temp_dir.path().join("resolv.conf");
+
+    let output = run_autodns(&config_path, &["check"]);
+    assert!(
+        output.status.success(),
+        "autodns check command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // The second Cloudflare server comes before Google in the list, but shares its provider
+    let dns_ips = read_resolv_conf(&resolv_path);
+    let expected: Vec<IpAddr> = vec!["1.1.1.1".parse().unwrap(), "8.8.8.8".parse().unwrap()];
+    assert_eq!(dns_ips, expected, "Expected one server per provider");
+
+    println!("✓ Test passed: diverse policy picked servers from different providers");
+}
+
+#[test]
+fn test_validate_warns_about_single_provider() {
+    // Test: validate should warn when every server belongs to the same provider
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        TestServer::new("Lab-1", "192.0.2.1").with("provider", "lab"),
+        TestServer::new("Lab-2", "192.0.2.2").with("group", "Lab"),
+    ];
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+
+    let output = run_autodns(&config_path, &["validate", "--json"]);
+    assert!(output.status.success(), "A single provider is only a warning");
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(report["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|d| d["message"].as_str().unwrap().contains("All DNS servers belong to lab")));
+
+    println!("✓ Test passed: validate warned about a single provider");
+}
+
+#[test]
+fn test_validate_rejects_family_policy_without_servers() {
+    // Test: a family policy that excludes every server should be an error
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Test-1", "192.0.2.1"),
+        ("Test-2", "192.0.2.2"),
+    ];
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    append_to_config(&config_path, "family_policy: ipv6_only\n");
+
+    let output = run_autodns(&config_path, &["validate", "--json"]);
+    assert!(!output.status.success(), "validate should fail when no server is usable");
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(report["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|d| d["severity"] == "error" && d["field"] == "family_policy"));
+
+    println!("✓ Test passed: validate rejected a family policy without servers");
+}
+
+#[test]
+fn test_validate_checks_server_scoring() {
+    // Test: weights must be positive, and settings of the other mode are reported
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        TestServer::new("Test-1", "192.0.2.1").with("weight", "0"),
+        TestServer::new("Test-2", "192.0.2.2").with("priority", "1"),
+    ];
+    let config_path = create_test_config(&temp_dir, "benchmark", dns_servers, 1);
+
+    let output = run_autodns(&config_path, &["validate"]);
+    assert!(!output.status.success(), "A weight of 0 should be an error");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("weight of Test-1 must be a number greater than 0"));
+    assert!(stdout.contains("priority is only used in firstonline and hybrid modes: Test-2"));
+
+    println!("✓ Test passed: validate checked the server scoring settings");
+}
+
+#[test]
+fn test_validate_hybrid_mode_tiers() {
+    // Test: hybrid mode accepts tiers, and warns when there is only one
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Internal", "192.0.2.1"),
+        ("Public", "192.0.2.2"),
+    ];
+    let config_path = create_test_config(&temp_dir, "hybrid", dns_servers, 1);
+
+    let output = run_autodns(&config_path, &["validate"]);
+    assert!(output.status.success(), "hybrid mode should be accepted");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("all servers have the same priority"));
+
+    let dns_servers = vec![
+        TestServer::new("Internal", "192.0.2.1"),
+        TestServer::new("Public", "192.0.2.2").with("tier", "1"),
+    ];
+    let config_path = create_test_config(&temp_dir, "hybrid", dns_servers, 1);
+
+    let output = run_autodns(&config_path, &["validate"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("same priority"), "Two tiers should not be reported: {}", stdout);
+    assert!(!stdout.contains("priority is only used"), "Tiers are used in hybrid mode: {}", stdout);
+
+    println!("✓ Test passed: hybrid mode tiers validated");
+}
+
+#[test]
+fn test_validate_limits_pinned_servers() {
+    // Test: pinned servers must leave room for the selection in resolv.conf
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        TestServer::new("Static-1", "192.0.2.1").with("pinned", "true"),
+        TestServer::new("Static-2", "192.0.2.2").with("pinned", "true"),
+        TestServer::new("Static-3", "192.0.2.3").with("pinned", "true"),
+        TestServer::new("Dynamic", "192.0.2.4"),
+    ];
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+
+    let output = run_autodns(&config_path, &["validate"]);
+    assert!(!output.status.success(), "3 pinned servers leave no room for the selection");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("3 servers are pinned"), "Unexpected output: {}", stdout);
+
+    println!("✓ Test passed: validate limited the pinned servers");
+}
+
+#[test]
+fn test_pinned_servers_written_first() {
+    // Test: pinned servers should be written before the other servers, whatever the probes say
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    // TEST-NET addresses never answer, so the fallback servers follow the pinned one
+    let dns_servers = vec![
+        TestServer::new("Unreachable-1", "192.0.2.1"),
+        TestServer::new("Static", "192.0.2.3").with("pinned", "true"),
+        TestServer::new("Unreachable-2", "192.0.2.2"),
+    ];
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    append_to_config(
+        &config_path,
+        "on_all_offline: fallback\nfallback_servers: [\"198.51.100.1\", \"198.51.100.2\"]\n",
+    );
+
+    let output = run_autodns(&config_path, &["check", "--output", "json"]);
+    assert!(
+        output.status.success(),
+        "autodns check command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let resolv_path = temp_dir.path().join("resolv.conf");
+    let dns_ips = read_resolv_conf(&resolv_path);
+    let expected: Vec<IpAddr> = vec![
+        "192.0.2.3".parse().unwrap(),
+        "198.51.100.1".parse().unwrap(),
+        "198.51.100.2".parse().unwrap(),
+    ];
+    assert_eq!(dns_ips, expected, "Expected the pinned server first");
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["selection"][0]["address"], "192.0.2.3", "The report should list the pinned server first");
+
+    println!("✓ Test passed: pinned servers written first");
+}
+
+#[test]
+fn test_fallback_servers_written_when_all_offline() {
+    // Test: with on_all_offline fallback, the fallback servers replace an all-offline selection
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    // TEST-NET addresses never answer
+    let dns_servers = vec![
+        ("Unreachable-1", "192.0.2.1"),
+        ("Unreachable-2", "192.0.2.2"),
+    ];
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    append_to_config(
+        &config_path,
+        "on_all_offline: fallback\nfallback_servers: [\"198.51.100.1\", \"198.51.100.2\"]\n",
+    );
+
+    let output = run_autodns(&config_path, &["check"]);
+    assert!(
+        output.status.success(),
+        "autodns check command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let resolv_path = temp_dir.path().join("resolv.conf");
+    let dns_ips = read_resolv_conf(&resolv_path);
+    let expected: Vec<IpAddr> = vec!["198.51.100.1".parse().unwrap(), "198.51.100.2".parse().unwrap()];
+    assert_eq!(dns_ips, expected, "Expected the fallback servers");
+
+    println!("✓ Test passed: fallback servers written when all servers are offline");
+}
+
+#[test]
+fn test_isolated_host_leaves_resolv_conf_alone() {
+    // Test: when the connectivity target is unreachable, nothing is blamed on the DNS servers
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let dns_servers = vec![
+        ("Unreachable-1", "192.0.2.1"),
+        ("Unreachable-2", "192.0.2.2"),
+    ];
+    let config_path = create_test_config(&temp_dir, "firstonline", dns_servers, 1);
+    append_to_config(
+        &config_path,
+        "on_all_offline: fallback\n\
+         fallback_servers: [\"198.51.100.1\"]\n\
+         connectivity:\n  targets: [\"192.0.2.77:443\"]\n  timeout_seconds: 1\n",
+    );
+
+    let output = run_autodns(&config_path, &["check"]);
+    assert!(!output.status.success(), "check should fail without connectivity");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Network unavailable"), "Unexpected error: {}", stderr);
+
+    // Neither the fallback servers nor anything else was written
+    let resolv_path = temp_dir.path().join("resolv.conf");
+    assert!(!resolv_path.exists(), "resolv.conf should not be written");
+
+    println!("✓ Test passed: an isolated host left resolv.conf alone");
+}